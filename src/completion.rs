@@ -0,0 +1,249 @@
+use crate::library::PREFECTURES;
+use crate::ndc::{self, NdcEdition};
+use crate::query::normalize;
+use crate::req::{LibGroup, ReqType};
+
+/// CQLのクエリー対象項目（レファレンス事例）
+const REFERENCE_INDEXES: &[&str] = &[
+    "anywhere",
+    "question",
+    "reg-id",
+    "answer",
+    "solution",
+    "keyword",
+    "ndc",
+    "res-type",
+    "con-type",
+    "bibl-desc",
+    "bibl-isbn",
+    "ans-proc",
+    "referral",
+    "pre-res",
+    "note",
+    "ptn-type",
+    "contri",
+    "sys-id",
+    "lib-name",
+];
+
+/// CQLのクエリー対象項目（調べ方マニュアル）
+const MANUAL_INDEXES: &[&str] = &[
+    "anywhere",
+    "theme",
+    "reg-id",
+    "guide",
+    "completion",
+    "keyword",
+    "ndc",
+    "bibl-desc",
+    "bibl-isbn",
+    "note",
+    "sys-id",
+    "lib-name",
+];
+
+/// CQLのクエリー対象項目（特別コレクション）
+const COLLECTION_INDEXES: &[&str] = &[
+    "anywhere",
+    "col-name",
+    "reg-id",
+    "outline",
+    "restriction",
+    "catalog",
+    "literature",
+    "number",
+    "continue",
+    "keyword",
+    "ndc",
+    "note",
+    "sys-id",
+    "lib-name",
+];
+
+/// CQLのクエリー対象項目（参加館プロファイル）
+const PROFILE_INDEXES: &[&str] = &[
+    "anywhere",
+    "lib-type",
+    "lib-name",
+    "address",
+    "open-info",
+    "restriction",
+    "outline",
+    "feature",
+    "notes",
+    "access",
+    "isil",
+];
+
+const REQ_TYPES: [ReqType; 5] = [
+    ReqType::Reference,
+    ReqType::Manual,
+    ReqType::Collection,
+    ReqType::Profile,
+    ReqType::All,
+];
+
+/// 検索対象で使えるCQLのクエリー対象項目
+///
+/// `all` は全ての検索対象で使える項目のみを返す。
+pub fn cql_indexes(ty: ReqType) -> Vec<&'static str> {
+    match ty {
+        ReqType::Reference => REFERENCE_INDEXES.to_vec(),
+        ReqType::Manual => MANUAL_INDEXES.to_vec(),
+        ReqType::Collection => COLLECTION_INDEXES.to_vec(),
+        ReqType::Profile => PROFILE_INDEXES.to_vec(),
+        ReqType::All => REFERENCE_INDEXES
+            .iter()
+            .copied()
+            .filter(|index| {
+                [MANUAL_INDEXES, COLLECTION_INDEXES, PROFILE_INDEXES]
+                    .iter()
+                    .all(|indexes| indexes.contains(index))
+            })
+            .collect(),
+    }
+}
+
+/// 候補のうち、値または見出しが入力に一致するものを、前方一致するものを先にして返す。
+///
+/// 全角・半角、旧字体・新字体、大文字・小文字の違いは無視する。
+fn filter<'a>(
+    candidates: impl IntoIterator<Item = (String, Vec<&'a str>)>,
+    value: &str,
+) -> Vec<String> {
+    let value = normalize(value.trim());
+    let mut prefix = Vec::new();
+    let mut partial = Vec::new();
+    for (candidate, labels) in candidates {
+        let keys: Vec<String> = std::iter::once(candidate.as_str())
+            .chain(labels)
+            .map(normalize)
+            .collect();
+        if keys.iter().any(|key| key.starts_with(&value)) {
+            prefix.push(candidate);
+        } else if keys.iter().any(|key| key.contains(&value)) {
+            partial.push(candidate);
+        }
+    }
+    prefix.extend(partial);
+    prefix
+}
+
+/// 検索対象(`type`)の補完
+pub fn req_types(value: &str) -> Vec<String> {
+    filter(REQ_TYPES.iter().map(|ty| (ty.to_string(), vec![])), value)
+}
+
+/// CQLのクエリー対象項目の補完。検索対象が未定の場合は、いずれかの検索対象で使える項目を返す。
+pub fn indexes(ty: Option<ReqType>, value: &str) -> Vec<String> {
+    let mut candidates: Vec<&str> = Vec::new();
+    for ty in ty.map_or(REQ_TYPES[..4].to_vec(), |ty| vec![ty]) {
+        for index in cql_indexes(ty) {
+            if !candidates.contains(&index) {
+                candidates.push(index);
+            }
+        }
+    }
+    filter(
+        candidates
+            .into_iter()
+            .map(|index| (index.to_string(), vec![])),
+        value,
+    )
+}
+
+/// 図書館グループの補完。グループの名前(例 公共図書館)でも照合する。
+pub fn lib_groups(value: &str) -> Vec<String> {
+    filter(
+        LibGroup::ALL
+            .iter()
+            .map(|group| (group.to_string(), vec![group.label()])),
+        value,
+    )
+}
+
+/// 都道府県名の補完
+pub fn prefectures(value: &str) -> Vec<String> {
+    filter(
+        PREFECTURES.iter().map(|pref| (pref.to_string(), vec![])),
+        value,
+    )
+}
+
+/// NDCの類・綱・要目の記号の補完。見出し(例 日本史)でも照合し、上位の区分から順に返す。
+pub fn ndc_codes(edition: NdcEdition, value: &str) -> Vec<String> {
+    let mut codes = ndc::children(edition, "");
+    let mut level = 0;
+    while level < codes.len() {
+        let end = codes.len();
+        for i in level..end {
+            let children = ndc::children(edition, &codes[i].0);
+            codes.extend(children);
+        }
+        level = end;
+    }
+    filter(
+        codes.into_iter().map(|(code, label)| (code, vec![label])),
+        value,
+    )
+}
+
+/// 図書館コードの補完。`libraries` は図書館コード・図書館名（正式）・図書館名（略式）で、図書館名でも照合する。
+pub fn lib_ids(libraries: &[(String, String, String)], value: &str) -> Vec<String> {
+    filter(
+        libraries
+            .iter()
+            .map(|(lib_id, name, abbr)| (lib_id.clone(), vec![name.as_str(), abbr.as_str()])),
+        value,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cql_indexes, indexes, lib_groups, lib_ids, ndc_codes, prefectures, req_types};
+    use crate::ndc::NdcEdition;
+    use crate::req::ReqType;
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(req_types("ma"), ["manual"]);
+        assert_eq!(cql_indexes(ReqType::All), ["anywhere", "lib-name"]);
+        assert_eq!(indexes(Some(ReqType::Manual), "th"), ["theme"]);
+        assert!(indexes(Some(ReqType::Profile), "ques").is_empty());
+        assert_eq!(indexes(None, "ques"), ["question"]);
+        assert_eq!(
+            indexes(Some(ReqType::Reference), "res"),
+            ["res-type", "pre-res"]
+        );
+
+        assert_eq!(lib_groups("pu"), ["public"]);
+        assert_eq!(lib_groups("大学"), ["academic"]);
+        assert_eq!(prefectures("東京"), ["東京都"]);
+        assert_eq!(prefectures("京都"), ["京都府", "東京都"]);
+
+        let codes = ndc_codes(NdcEdition::Ndc10, "2");
+        assert_eq!(codes[..3], ["2", "20", "21"]);
+        assert!(codes.contains(&"291".to_string()));
+        assert_eq!(ndc_codes(NdcEdition::Ndc10, "日本史")[0], "21");
+        assert_eq!(
+            ndc_codes(NdcEdition::Ndc10, "").len(),
+            ndc_codes(NdcEdition::Ndc9, "").len()
+        );
+
+        let libraries = vec![
+            (
+                "1110001".to_string(),
+                "北海道立図書館".to_string(),
+                "北海道立".to_string(),
+            ),
+            (
+                "1310001".to_string(),
+                "東京都立中央図書館".to_string(),
+                "都立中央".to_string(),
+            ),
+        ];
+        assert_eq!(lib_ids(&libraries, "131"), ["1310001"]);
+        assert_eq!(lib_ids(&libraries, "北海道"), ["1110001"]);
+        assert_eq!(lib_ids(&libraries, "").len(), 2);
+    }
+}
@@ -1,17 +1,56 @@
-use crate::req::CrdSearchRequest;
+use crate::date::{CrdDate, CrdDateTime};
+use crate::req::{CrdSearchRequest, LibType};
 use crate::service::CrdService;
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use quick_xml::Reader;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 
 const CRD_API_BASE_URL: &str = "https://crd.ndl.go.jp/api/refsearch";
+/// CRD APIへの連続アクセスの最小間隔
+const CRD_API_MIN_INTERVAL: Duration = Duration::from_millis(300);
+
+/// CRD APIへのアクセス間隔を制御する。
+///
+/// ページングなどで連続してAPIを呼び出す場合でも、サービス全体で
+/// `CRD_API_MIN_INTERVAL` 以上の間隔を空ける。
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    last: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RateLimiter {
+    pub async fn wait(&self) {
+        let mut last = self.last.lock().await;
+        if let Some(at) = *last {
+            tokio::time::sleep_until(at + CRD_API_MIN_INTERVAL).await;
+        }
+        *last = Some(Instant::now());
+    }
+}
 
 impl CrdService {
     pub async fn crd_search(&self, request: CrdSearchRequest) -> anyhow::Result<CrdResultSet> {
+        let raw_xml = self.crd_search_raw(request).await?;
+        let set = CrdResultSet::parse(&raw_xml)?;
+        self.vocabulary.0.lock().await.add_result_set(&set);
+        Ok(set)
+    }
+
+    /// CRD APIを呼び出し、レスポンスのXMLをそのまま返す。
+    pub async fn crd_search_raw(&self, request: CrdSearchRequest) -> anyhow::Result<String> {
         let CrdSearchRequest {
             ty,
             condition,
             lib_id,
             lib_group,
+            lib_type,
             results_get_position,
             results_num,
         } = request;
@@ -19,8 +58,16 @@ impl CrdService {
             ("type", ty.to_string()),
             ("results_num", results_num.to_string()),
         ];
-        if let Some(query) = &condition.query {
-            queries.push(("query", query.to_string()));
+        // lib_typeとtype・lib_groupの組み合わせは、CrdSearchRequest::validateで検証済みとする。
+        let query = match (&condition.query, lib_type) {
+            (Some(query), Some(lib_type)) => {
+                Some(format!("({}) and lib-type = {}", query, lib_type))
+            }
+            (None, Some(lib_type)) => Some(format!("lib-type = {}", lib_type)),
+            (query, None) => query.clone(),
+        };
+        if let Some(query) = query {
+            queries.push(("query", query));
         }
         if let Some(query) = &condition.crt_date_from {
             queries.push(("crt-date_from", query.to_string()));
@@ -37,7 +84,7 @@ impl CrdService {
         if let Some(query) = &condition.lst_date_from {
             queries.push(("lst-date_from", query.to_string()));
         }
-        if let Some(query) = &condition.lst_date_from {
+        if let Some(query) = &condition.lst_date_to {
             queries.push(("lst-date_to", query.to_string()));
         }
         if let Some(lib_id) = lib_id {
@@ -57,15 +104,53 @@ impl CrdService {
             .header("User-Agent", format!("crd/{}", env!("CARGO_PKG_VERSION")))
             .build()?;
 
+        self.limiter.wait().await;
         let raw_xml = self.http.execute(req).await?.text().await?;
-        let result: CrdResultSet = quick_xml::de::from_str(&raw_xml)?;
 
-        Ok(result)
+        Ok(raw_xml)
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// レスポンスのXMLから、`<result>` 要素ごとのXML断片を切り出す。
+pub fn split_results(raw_xml: &str) -> anyhow::Result<Vec<&str>> {
+    let mut reader = Reader::from_str(raw_xml);
+    let mut fragments = Vec::new();
+    let mut depth = 0;
+    let mut start = None;
+    loop {
+        let position = reader.buffer_position() as usize;
+        match reader.read_event()? {
+            Event::Start(e) => {
+                depth += 1;
+                if depth == 2 && e.name().as_ref() == b"result" {
+                    start = Some(position);
+                }
+            }
+            // 空の `<result/>` も読み込めないレコードとして残す
+            Event::Empty(e) if depth == 1 && e.name().as_ref() == b"result" => {
+                fragments.push(&raw_xml[position..reader.buffer_position() as usize]);
+            }
+            Event::End(_) => {
+                if depth == 2
+                    && let Some(start) = start.take()
+                {
+                    fragments.push(&raw_xml[start..reader.buffer_position() as usize]);
+                }
+                depth -= 1;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(fragments)
+}
+
+/// `<result>` 要素のXML断片を1件のレコードとして読み込む。
+pub fn parse_result(fragment: &str) -> Result<CrdResult, quick_xml::DeError> {
+    quick_xml::de::from_str::<ResultEntry>(fragment).map(|entry| entry.item)
+}
 
+#[derive(Deserialize, Debug, Clone)]
 pub struct CrdResultSet {
     /// ヒット数
     pub hit_num: Option<i32>,
@@ -84,8 +169,109 @@ pub struct CrdResultSet {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub err_list: Option<Vec<ErrorEntry>>,
     /// レファレンス事例リスト
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub result: Option<Vec<ResultEntry>>,
+    ///
+    /// `<result>` 要素ごとに読み込むため、デシリアライズの対象としない。`CrdResultSet::parse` で設定する。
+    #[serde(skip)]
+    pub result: Vec<Result<CrdResult, Box<MalformedResult>>>,
+}
+
+impl CrdResultSet {
+    /// レスポンスのXMLを読み込む。
+    ///
+    /// `<result>` 要素は1件ずつ読み込み、読み込めなかったレコードは `MalformedResult` として残す。
+    /// 1件の不正なレコードのためにページ全体を失わないようにする。
+    pub fn parse(raw_xml: &str) -> anyhow::Result<CrdResultSet> {
+        let mut set: CrdResultSet = quick_xml::de::from_str(raw_xml)?;
+        set.result = split_results(raw_xml)?
+            .into_iter()
+            .map(|fragment| {
+                parse_result(fragment)
+                    .map_err(|e| Box::new(MalformedResult::new(fragment, e.to_string())))
+            })
+            .collect();
+        Ok(set)
+    }
+}
+
+/// 読み込めなかった `<result>` 要素
+///
+/// 読み取れた範囲で、URLやシステム管理項目を記録する。
+#[derive(Debug, Clone)]
+pub struct MalformedResult {
+    /// レコードの種類の要素名(`reference` など)
+    pub ty: Option<String>,
+    pub url: Option<String>,
+    pub sys_id: Option<String>,
+    pub lib_id: Option<String>,
+    pub lib_name: Option<String>,
+    pub reg_date: Option<String>,
+    pub lst_date: Option<String>,
+    /// デシリアライズのエラー
+    pub error: String,
+    /// `<result>` 要素のXML
+    pub raw_xml: String,
+}
+
+impl MalformedResult {
+    pub fn new(fragment: &str, error: String) -> MalformedResult {
+        let mut partial = MalformedResult {
+            ty: None,
+            url: None,
+            sys_id: None,
+            lib_id: None,
+            lib_name: None,
+            reg_date: None,
+            lst_date: None,
+            error,
+            raw_xml: fragment.to_string(),
+        };
+        let mut reader = Reader::from_str(fragment);
+        let mut depth = 0;
+        let mut current = Vec::new();
+        let mut text = String::new();
+        while let Ok(event) = reader.read_event() {
+            match event {
+                Event::Start(e) => {
+                    depth += 1;
+                    current = e.name().as_ref().to_vec();
+                    text.clear();
+                    if depth == 2 {
+                        partial.ty = Some(String::from_utf8_lossy(&current).into_owned());
+                    }
+                }
+                Event::Text(e) => text.push_str(&e.decode().unwrap_or_default()),
+                Event::GeneralRef(e) => {
+                    if let Some(c) = e
+                        .decode()
+                        .ok()
+                        .and_then(|name| resolve_predefined_entity(&name))
+                    {
+                        text.push_str(c);
+                    }
+                }
+                Event::End(_) => {
+                    depth -= 1;
+                    let field = match current.as_slice() {
+                        b"url" => &mut partial.url,
+                        b"sys-id" => &mut partial.sys_id,
+                        b"lib-id" => &mut partial.lib_id,
+                        b"lib-name" => &mut partial.lib_name,
+                        b"reg-date" => &mut partial.reg_date,
+                        b"lst-date" => &mut partial.lst_date,
+                        _ => &mut None,
+                    };
+                    if field.is_none() {
+                        *field = Some(text.trim().to_string());
+                    }
+                    current.clear();
+                    text.clear();
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        partial
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -135,9 +321,10 @@ pub struct CrdReferenceResult {
     pub answer: String,
     #[serde(rename = "crt-date", default)]
     // todo: crt-dateは本来は必須項目であるが、存在しないデータがあるためdefaultを付与
-    pub crt_date: String,
+    pub crt_date: CrdDate,
     /// 解決/未解決, 0: 解決, 1: 未解決
-    pub solution: Option<i8>,
+    #[serde(default)]
+    pub solution: Solution,
     #[serde(rename = "keyword")]
     pub keywords: Option<Vec<String>>,
     pub classes: Option<Vec<NdcClass>>,
@@ -185,9 +372,10 @@ pub struct CrdManualResult {
     pub guide: String,
     /// 調べ方作成日
     #[serde(rename = "crt-date")]
-    pub crt_date: String,
+    pub crt_date: CrdDate,
     /// 完成/未完成, 0: 完成, 1: 未完成
-    pub completion: Option<i8>,
+    #[serde(default)]
+    pub completion: Completion,
     #[serde(rename = "keyword")]
     pub keywords: Option<Vec<String>>,
     pub classes: Option<Vec<NdcClass>>,
@@ -226,7 +414,8 @@ pub struct CrdCollectionResult {
     /// 継続
     ///
     /// 0（継続有）, 1（継続無）
-    pub r#continue: Option<String>,
+    #[serde(default)]
+    pub r#continue: Continuation,
     #[serde(rename = "keyword")]
     pub keywords: Option<Vec<String>>,
     pub classes: Option<Vec<NdcClass>>,
@@ -240,8 +429,8 @@ pub struct CrdCollectionResult {
 pub struct CrdProfileResult {
     pub url: String,
     /// 館種コード
-    #[serde(rename = "lib-type")]
-    pub ty: String,
+    #[serde(rename = "lib-type", deserialize_with = "deserialize_lib_type")]
+    pub ty: LibType,
     /// 図書館名（正式）
     #[serde(rename = "lib-name")]
     pub name: String,
@@ -308,10 +497,10 @@ pub struct CrdProfileResult {
 pub struct CrdSystemWithoutSysId {
     /// 登録日時
     #[serde(rename = "reg-date")]
-    pub reg_date: String,
+    pub reg_date: CrdDateTime,
     /// 最終更新日時
     #[serde(rename = "lst-date")]
-    pub lst_date: String,
+    pub lst_date: CrdDateTime,
     /// 提供館コード
     #[serde(rename = "lib-id")]
     pub lib_id: String,
@@ -330,10 +519,10 @@ pub struct CrdSystemWithoutSysId {
 pub struct CrdSystem {
     /// 登録日時
     #[serde(rename = "reg-date")]
-    pub reg_date: String,
+    pub reg_date: CrdDateTime,
     /// 最終更新日時
     #[serde(rename = "lst-date")]
-    pub lst_date: String,
+    pub lst_date: CrdDateTime,
     /// 登録番号
     #[serde(rename = "sys-id")]
     pub sys_id: String,
@@ -355,24 +544,249 @@ pub struct CrdSystem {
 pub struct NdcClass {
     /// 分類の種類(「NDC」のみ)
     #[serde(rename = "@type")]
-    ty: String,
+    pub ty: String,
     /// typeで指定された分類のバージョン(例 9(9版を示す))
     #[serde(rename = "@code", skip_serializing_if = "Option::is_none")]
-    version: Option<String>,
+    pub version: Option<String>,
     /// 分類の番号
     #[serde(rename = "$text", skip_serializing_if = "Option::is_none")]
-    value: Option<String>,
+    pub value: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Bibl {
     /// 書誌的事項(参考資料)
     #[serde(rename = "bibl-desc", skip_serializing_if = "Option::is_none")]
-    desc: Option<String>,
+    pub desc: Option<String>,
     /// ISBN（参考資料）
     #[serde(rename = "bibl-isbn", skip_serializing_if = "Option::is_none")]
-    isbn: Option<String>,
+    pub isbn: Option<String>,
     /// 備考(参考資料)
     #[serde(rename = "bibl-note", skip_serializing_if = "Option::is_none")]
-    note: Option<String>,
+    pub note: Option<String>,
+}
+
+/// 館種コード表にないコードも `LibType::Unknown` として読み込む。
+fn deserialize_lib_type<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LibType, D::Error> {
+    Ok(LibType::from_code(&String::deserialize(deserializer)?))
+}
+
+/// 0/1で表される区分の値を判定する。
+///
+/// `0`・`1` のほか、`zero`・`one` に挙げた文字列表記(大文字・小文字は区別しない)を受け付ける。
+/// 値がない場合と判定できない場合は `None` を返す。
+fn parse_flag(value: Option<&str>, zero: &[&str], one: &[&str]) -> Option<u8> {
+    let value = value.map(str::trim).filter(|v| !v.is_empty())?;
+    let lower = value.to_lowercase();
+    if lower == "0" || zero.contains(&lower.as_str()) {
+        Some(0)
+    } else if lower == "1" || one.contains(&lower.as_str()) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// 0/1で表される区分の型を定義する。
+///
+/// 0・1とそれぞれの文字列表記を受け付け、判定できない値は `Unknown` にそのまま保持する。
+/// 文字列表記の先頭を出力の表記とし、`Unknown` は `unknown` または `unknown:<値>` と出力する。
+macro_rules! flag {
+    (
+        $(#[$meta:meta])*
+        $name:ident {
+            $(#[$zero_meta:meta])*
+            $zero:ident = [$zero_name:literal $(, $zero_alias:literal)* $(,)?],
+            $(#[$one_meta:meta])*
+            $one:ident = [$one_name:literal $(, $one_alias:literal)* $(,)?] $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum $name {
+            $(#[$zero_meta])*
+            $zero,
+            $(#[$one_meta])*
+            $one,
+            /// 0・1以外の値。値がない場合は `None`。
+            Unknown(Option<String>),
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                $name::Unknown(None)
+            }
+        }
+
+        impl $name {
+            /// CRDの区分値。不明な場合は `None`。
+            pub fn code(&self) -> Option<i8> {
+                match self {
+                    $name::$zero => Some(0),
+                    $name::$one => Some(1),
+                    $name::Unknown(_) => None,
+                }
+            }
+
+            /// 判定できなかった値。値がない場合と判定できた場合は `None`。
+            pub fn unknown_value(&self) -> Option<&str> {
+                match self {
+                    $name::Unknown(value) => value.as_deref(),
+                    _ => None,
+                }
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    $name::$zero => f.write_str($zero_name),
+                    $name::$one => f.write_str($one_name),
+                    $name::Unknown(None) => f.write_str("unknown"),
+                    $name::Unknown(Some(value)) => write!(f, "unknown:{}", value),
+                }
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl JsonSchema for $name {
+            fn schema_name() -> Cow<'static, str> {
+                stringify!($name).into()
+            }
+
+            fn json_schema(_: &mut SchemaGenerator) -> Schema {
+                json_schema!({
+                    "type": "string",
+                    "description": concat!(
+                        $zero_name, "(0)、", $one_name, "(1)、",
+                        "判定できない場合は unknown(値がある場合は unknown:<値>)"
+                    ),
+                })
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = Option::<String>::deserialize(deserializer)?;
+                Ok(
+                    match parse_flag(
+                        value.as_deref(),
+                        &[$zero_name, $($zero_alias),*],
+                        &[$one_name, $($one_alias),*],
+                    ) {
+                        Some(0) => $name::$zero,
+                        Some(_) => $name::$one,
+                        None => $name::Unknown(
+                            value
+                                .map(|v| v.trim().to_string())
+                                .filter(|v| !v.is_empty()),
+                        ),
+                    },
+                )
+            }
+        }
+    };
+}
+
+flag! {
+    /// 解決/未解決
+    Solution {
+        /// 解決(0)
+        Resolved = ["resolved", "解決"],
+        /// 未解決(1)
+        Unresolved = ["unresolved", "未解決"],
+    }
+}
+
+flag! {
+    /// 完成/未完成
+    Completion {
+        /// 完成(0)
+        Completed = ["completed", "complete", "完成"],
+        /// 未完成(1)
+        Uncompleted = ["uncompleted", "incomplete", "未完成"],
+    }
+}
+
+flag! {
+    /// 継続/非継続
+    Continuation {
+        /// 継続有(0)
+        Continued = ["continued", "継続", "継続有"],
+        /// 継続無(1)
+        Discontinued = ["discontinued", "非継続", "継続無"],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Completion, Continuation, CrdResult, CrdResultSet, Solution, parse_result, split_results,
+    };
+
+    const REFERENCE_XML: &str = include_str!("fixtures/reference.xml");
+
+    #[test]
+    fn test_split_results() {
+        let set = CrdResultSet::parse(REFERENCE_XML).unwrap();
+        let fragments = split_results(REFERENCE_XML).unwrap();
+        assert_eq!(fragments.len(), set.result.len());
+        assert!(fragments[0].starts_with("<result>"));
+        assert!(fragments[1].ends_with("</result>"));
+
+        let CrdResult::Reference(reference) = parse_result(fragments[1]).unwrap() else {
+            panic!("expected a reference record");
+        };
+        assert_eq!(reference.system.sys_id, "1000300002");
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let xml = REFERENCE_XML.replacen("<file-num>0</file-num>", "<file-num>x</file-num>", 1);
+        let set = CrdResultSet::parse(&xml).unwrap();
+        assert_eq!(set.result.len(), 2);
+        let malformed = set.result[0].as_ref().unwrap_err();
+        assert_eq!(malformed.ty.as_deref(), Some("reference"));
+        assert_eq!(malformed.sys_id.as_deref(), Some("1000300001"));
+        assert_eq!(malformed.lib_id.as_deref(), Some("2110001"));
+        assert_eq!(
+            malformed.url.as_deref(),
+            Some("https://crd.ndl.go.jp/reference/detail?page=ref_view&id=1000300001")
+        );
+        assert!(malformed.raw_xml.starts_with("<result>"));
+        assert!(set.result[1].is_ok());
+    }
+
+    #[test]
+    fn test_flags() {
+        let parse = |xml: &str| match parse_result(xml).unwrap() {
+            CrdResult::Reference(reference) => reference.solution,
+            _ => panic!("expected a reference record"),
+        };
+        let fragments = split_results(REFERENCE_XML).unwrap();
+        assert_eq!(parse(fragments[0]), Solution::Resolved);
+        assert_eq!(parse(fragments[1]), Solution::Unresolved);
+        assert_eq!(
+            parse(&fragments[1].replace("<solution>1</solution>", "<solution>Resolved</solution>")),
+            Solution::Resolved
+        );
+        assert_eq!(
+            parse(&fragments[1].replace("<solution>1</solution>", "<solution>2</solution>")),
+            Solution::Unknown(Some("2".to_string()))
+        );
+        assert_eq!(
+            parse(&fragments[1].replace("<solution>1</solution>", "")),
+            Solution::Unknown(None)
+        );
+
+        let completion: Completion = serde_json::from_value("未完成".into()).unwrap();
+        assert_eq!(completion, Completion::Uncompleted);
+        let continuation: Continuation = serde_json::from_value("0".into()).unwrap();
+        assert_eq!(continuation.code(), Some(0));
+    }
 }
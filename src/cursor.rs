@@ -0,0 +1,251 @@
+use crate::req::{CrdSearchRequest, ReqType, SearchRequest, SortKey, SortOrder};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+
+/// カーソルの形式のバージョン
+const CURSOR_VERSION: u8 = 1;
+
+/// FNV-1a(64bit)
+pub(crate) fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// カーソルの比較のために検索条件を正規化する。
+///
+/// 取得位置を除き、検索式の前後・連続する空白の違いを無視する。
+pub fn normalize(search: &CrdSearchRequest) -> CrdSearchRequest {
+    let mut search = search.clone();
+    search.results_get_position = None;
+    search.condition.query = search
+        .condition
+        .query
+        .map(|query| query.split_whitespace().collect::<Vec<_>>().join(" "));
+    search
+}
+
+/// 検索の続きを取得するためのカーソル
+///
+/// 正規化した検索条件・並べ替えの条件と取得位置を含み、base64urlでエンコードした文字列として返す。
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchCursor {
+    #[serde(rename = "v")]
+    version: u8,
+    search: CrdSearchRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sort_by: Option<SortKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    order: Option<SortOrder>,
+    #[serde(default)]
+    include_library: bool,
+    offset: i32,
+    /// 検索条件の指紋。検索条件の比較と、破損・切り詰められたカーソルの検出に使う。
+    ///
+    /// 鍵を使わないハッシュのため、意図的な書き換えは検出できない。
+    /// カーソルの検索条件は利用者が直接指定できる検索条件と同じ扱いとし、秘匿や認可には使わない。
+    #[serde(rename = "fp")]
+    fingerprint: String,
+}
+
+impl SearchCursor {
+    pub fn new(
+        search: &CrdSearchRequest,
+        sort_by: Option<SortKey>,
+        order: Option<SortOrder>,
+        include_library: bool,
+    ) -> SearchCursor {
+        let offset = search.results_get_position.unwrap_or(0);
+        let search = normalize(search);
+        let mut cursor = SearchCursor {
+            version: CURSOR_VERSION,
+            search,
+            sort_by,
+            order,
+            include_library,
+            offset,
+            fingerprint: String::new(),
+        };
+        cursor.fingerprint = cursor.compute_fingerprint();
+        cursor
+    }
+
+    fn compute_fingerprint(&self) -> String {
+        let key = serde_json::json!([self.search, self.sort_by, self.order]).to_string();
+        format!("{:016x}", fnv1a(&key))
+    }
+
+    /// 検索条件からカーソルを作る。
+    ///
+    /// `cursor` を指定した場合はカーソルの検索条件を使い、取得位置をカーソルの位置とする。
+    /// 検索条件や並べ替えの条件を併せて指定した場合は、カーソルの条件と一致しなければエラーとする。
+    pub fn from_request(request: SearchRequest) -> Result<SearchCursor, String> {
+        let SearchRequest {
+            search,
+            cursor,
+            include_library,
+            sort_by,
+            order,
+            results_num_by_type: _,
+            clauses: _,
+            expand: _,
+            relax: _,
+        } = request;
+        let Some(token) = cursor else {
+            let search = search.ok_or("検索条件またはcursorを指定してください")?;
+            return Ok(SearchCursor::new(&search, sort_by, order, include_library));
+        };
+        let mut cursor = SearchCursor::decode(&token)?;
+        if let Some(search) = search {
+            let given = SearchCursor::new(&search, cursor.sort_by, cursor.order, false);
+            if given.fingerprint != cursor.fingerprint {
+                return Err(
+                    "cursorの検索条件と指定された検索条件が一致しません。新しい検索として取得し直してください"
+                        .to_string(),
+                );
+            }
+        }
+        if sort_by.is_some_and(|s| Some(s) != cursor.sort_by)
+            || order.is_some_and(|o| Some(o) != cursor.order)
+        {
+            return Err(
+                "cursorの並べ替えの条件と指定された条件が一致しません。新しい検索として取得し直してください"
+                    .to_string(),
+            );
+        }
+        cursor.include_library |= include_library;
+        Ok(cursor)
+    }
+
+    pub fn decode(token: &str) -> Result<SearchCursor, String> {
+        let invalid = || "cursorが正しくありません".to_string();
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token.trim())
+            .map_err(|_| invalid())?;
+        let cursor: SearchCursor = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        if cursor.version != CURSOR_VERSION || cursor.fingerprint != cursor.compute_fingerprint() {
+            return Err(invalid());
+        }
+        Ok(cursor)
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_string(self).unwrap())
+    }
+
+    /// 現在の取得位置を指定した検索条件
+    pub fn request(&self) -> CrdSearchRequest {
+        CrdSearchRequest {
+            results_get_position: Some(self.offset),
+            ..self.search.clone()
+        }
+    }
+
+    pub fn sort_by(&self) -> Option<SortKey> {
+        self.sort_by
+    }
+
+    pub fn order(&self) -> Option<SortOrder> {
+        self.order
+    }
+
+    /// 検索条件と並べ替えの条件の指紋。取得位置によらず同じ値となる。
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    pub fn include_library(&self) -> bool {
+        self.include_library
+    }
+
+    /// 検索対象を `ty` に、検索結果返却件数を `results_num` に変えたカーソル
+    pub fn for_type(&self, ty: ReqType, results_num: i8) -> SearchCursor {
+        let search = CrdSearchRequest {
+            ty,
+            results_num,
+            results_get_position: Some(self.offset),
+            ..self.search.clone()
+        };
+        SearchCursor::new(&search, self.sort_by, self.order, self.include_library)
+    }
+
+    /// 検索式を `query` に変えたカーソル
+    pub fn with_query(&self, query: String) -> SearchCursor {
+        let mut search = self.request();
+        search.condition.query = Some(query);
+        SearchCursor::new(&search, self.sort_by, self.order, self.include_library)
+    }
+
+    /// 取得位置を `offset` に進めたカーソル
+    pub fn with_offset(&self, offset: i32) -> SearchCursor {
+        SearchCursor {
+            offset,
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchCursor;
+    use crate::req::SearchRequest;
+
+    fn request(value: serde_json::Value) -> SearchRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_cursor() {
+        let cursor = SearchCursor::from_request(request(serde_json::json!({
+            "type": "reference",
+            "query": "question  any 北海道 ",
+            "results_num": 10,
+            "sort_by": "created_at",
+        })))
+        .unwrap();
+        let token = cursor.with_offset(10).encode();
+        assert!(!token.contains(['+', '/', '=']));
+
+        let next =
+            SearchCursor::from_request(request(serde_json::json!({ "cursor": token }))).unwrap();
+        assert_eq!(next.request().results_get_position, Some(10));
+        assert_eq!(next.request().results_num, 10);
+        assert_eq!(
+            next.request().condition.query.as_deref(),
+            Some("question any 北海道")
+        );
+        assert!(next.sort_by().is_some());
+
+        // 同じ検索条件(空白の違いは無視する)は受け付ける
+        assert!(
+            SearchCursor::from_request(request(serde_json::json!({
+                "cursor": token,
+                "type": "reference",
+                "query": "question any 北海道",
+                "results_num": 10,
+            })))
+            .is_ok()
+        );
+        // 異なる検索条件は拒否する
+        assert!(
+            SearchCursor::from_request(request(serde_json::json!({
+                "cursor": token,
+                "type": "reference",
+                "query": "question any 札幌",
+                "results_num": 10,
+            })))
+            .is_err()
+        );
+        assert!(
+            SearchCursor::from_request(request(serde_json::json!({
+                "cursor": token,
+                "sort_by": "lst_date",
+            })))
+            .is_err()
+        );
+        assert!(
+            SearchCursor::from_request(request(serde_json::json!({ "cursor": "abc" }))).is_err()
+        );
+    }
+}
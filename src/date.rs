@@ -0,0 +1,218 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+
+/// CRDの日時の書式。タイムゾーンの指定はなく、日本標準時として扱う。
+const DATETIME_FORMATS: &[&str] = &[
+    "%Y/%m/%d %H:%M:%S",
+    "%Y/%m/%d %H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y%m%d%H%M%S",
+];
+
+/// CRDの日付の書式
+const DATE_FORMATS: &[&str] = &["%Y%m%d", "%Y/%m/%d", "%Y-%m-%d", "%Y.%m.%d"];
+
+/// 日本標準時
+pub fn jst() -> FixedOffset {
+    FixedOffset::east_opt(9 * 3600).unwrap()
+}
+
+/// 日本標準時での今日の日付
+pub fn today() -> NaiveDate {
+    Utc::now().with_timezone(&jst()).date_naive()
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+}
+
+fn parse_datetime(value: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some(datetime.with_timezone(&jst()));
+    }
+    DATETIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| parse_date(value).map(|date| date.and_time(NaiveTime::MIN)))
+        .and_then(|datetime| datetime.and_local_timezone(jst()).single())
+}
+
+/// CRDの日付(事例作成日など)
+///
+/// 元の文字列を保持し、読み取れた場合は日付として扱う。レスポンスでは `value` にISO 8601の日付を出力する。
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CrdDate {
+    /// CRDに登録された文字列
+    pub raw: String,
+    /// 読み取った日付。空や書式の誤りで読み取れない場合は `None`。
+    pub value: Option<NaiveDate>,
+}
+
+impl CrdDate {
+    pub fn new(raw: impl Into<String>) -> CrdDate {
+        let raw = raw.into();
+        let value = parse_date(raw.trim())
+            .or_else(|| parse_datetime(raw.trim()).map(|datetime| datetime.date_naive()));
+        CrdDate { raw, value }
+    }
+}
+
+/// 読み取れた場合は `2023-04-01`、読み取れない場合は元の文字列で表示する。
+impl Display for CrdDate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "{}", value.format("%Y-%m-%d")),
+            None => write!(f, "{}", self.raw),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CrdDate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(CrdDate::new(
+            Option::<String>::deserialize(deserializer)?.unwrap_or_default(),
+        ))
+    }
+}
+
+impl Serialize for CrdDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CrdDate", 2)?;
+        match self.value {
+            Some(value) => state.serialize_field("value", &value.format("%Y-%m-%d").to_string())?,
+            None => state.skip_field("value")?,
+        }
+        state.serialize_field("raw", &self.raw)?;
+        state.end()
+    }
+}
+
+impl JsonSchema for CrdDate {
+    fn schema_name() -> Cow<'static, str> {
+        "CrdDate".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "object",
+            "description": "日付。valueはISO 8601の日付(読み取れない場合は省略)、rawはCRDに登録された文字列。",
+            "properties": {
+                "value": { "type": "string", "format": "date" },
+                "raw": { "type": "string" }
+            },
+            "required": ["raw"]
+        })
+    }
+}
+
+/// CRDの日時(登録日時・最終更新日時)
+///
+/// 元の文字列を保持し、日本標準時の日時として読み取る。レスポンスでは `value` にISO 8601の日時を出力する。
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CrdDateTime {
+    /// CRDに登録された文字列
+    pub raw: String,
+    /// 読み取った日時(日本標準時)。読み取れない場合は `None`。
+    pub value: Option<DateTime<FixedOffset>>,
+}
+
+impl CrdDateTime {
+    pub fn new(raw: impl Into<String>) -> CrdDateTime {
+        let raw = raw.into();
+        let value = parse_datetime(raw.trim());
+        CrdDateTime { raw, value }
+    }
+}
+
+/// 読み取れた場合は `2023-04-05T10:15:30+09:00`、読み取れない場合は元の文字列で表示する。
+impl Display for CrdDateTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "{}", value.to_rfc3339()),
+            None => write!(f, "{}", self.raw),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CrdDateTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(CrdDateTime::new(
+            Option::<String>::deserialize(deserializer)?.unwrap_or_default(),
+        ))
+    }
+}
+
+impl Serialize for CrdDateTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("CrdDateTime", 2)?;
+        match self.value {
+            Some(value) => state.serialize_field("value", &value.to_rfc3339())?,
+            None => state.skip_field("value")?,
+        }
+        state.serialize_field("raw", &self.raw)?;
+        state.end()
+    }
+}
+
+impl JsonSchema for CrdDateTime {
+    fn schema_name() -> Cow<'static, str> {
+        "CrdDateTime".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "object",
+            "description": "日時。valueはISO 8601の日時(日本標準時、読み取れない場合は省略)、rawはCRDに登録された文字列。",
+            "properties": {
+                "value": { "type": "string", "format": "date-time" },
+                "raw": { "type": "string" }
+            },
+            "required": ["raw"]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CrdDate, CrdDateTime};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_date() {
+        let date = CrdDate::new("20230401");
+        assert_eq!(date.value, NaiveDate::from_ymd_opt(2023, 4, 1));
+        assert_eq!(date.to_string(), "2023-04-01");
+        assert_eq!(CrdDate::new("2023/04/01").value, date.value);
+        assert_eq!(CrdDate::new("2023/04/01 10:00:00").value, date.value);
+
+        let empty = CrdDate::new("");
+        assert_eq!(empty.value, None);
+        assert_eq!(
+            serde_json::to_value(&empty).unwrap(),
+            serde_json::json!({ "raw": "" })
+        );
+    }
+
+    #[test]
+    fn test_datetime() {
+        let datetime = CrdDateTime::new("2023/04/05 10:15:30");
+        assert_eq!(datetime.to_string(), "2023-04-05T10:15:30+09:00");
+        assert_eq!(
+            serde_json::to_value(&datetime).unwrap(),
+            serde_json::json!({ "value": "2023-04-05T10:15:30+09:00", "raw": "2023/04/05 10:15:30" })
+        );
+        assert_eq!(
+            CrdDateTime::new("2023-04-05T01:15:30Z").value,
+            datetime.value
+        );
+        assert!(CrdDateTime::new("2023/04/05").value < datetime.value);
+        assert_eq!(CrdDateTime::new("不明").value, None);
+    }
+}
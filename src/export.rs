@@ -0,0 +1,565 @@
+pub mod bibliography;
+
+use crate::date::CrdDateTime;
+use crate::export::bibliography::BibliographyFormat;
+use crate::req::{Condition, CrdSearchRequest, LibGroup, LibType, ReqType};
+use crate::res::{BibliographyResponse, CrdSearchResult, ExportResponse, ExportedFile};
+use crate::service::CrdService;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// 一覧の項目を連結する区切り文字
+const LIST_SEPARATOR: &str = "; ";
+
+/// 出力形式
+#[derive(Serialize, Deserialize, JsonSchema, clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// CSV(RFC 4180)
+    Csv,
+    /// 1行に1件のJSON
+    Jsonl,
+    /// Excelで開けるタブ区切り(UTF-8 BOM付き、項目内のタブ・改行は空白に置換)
+    Tsv,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Tsv => "tsv",
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ExportArgs {
+    /// 検索対象(reference, manual, collection, profile, all)
+    #[arg(long = "type", value_name = "TYPE", default_value = "reference")]
+    pub ty: ReqType,
+    /// 検索条件(CQL)
+    #[arg(long)]
+    pub query: Option<String>,
+    /// 事例作成日FROM(YYYYMMDD)
+    #[arg(long)]
+    pub crt_date_from: Option<String>,
+    /// 事例作成日TO(YYYYMMDD)
+    #[arg(long)]
+    pub crt_date_to: Option<String>,
+    /// 登録日FROM(YYYYMMDD)
+    #[arg(long)]
+    pub reg_date_from: Option<String>,
+    /// 登録日TO(YYYYMMDD)
+    #[arg(long)]
+    pub reg_date_to: Option<String>,
+    /// 最終更新日FROM(YYYYMMDD)
+    #[arg(long)]
+    pub lst_date_from: Option<String>,
+    /// 最終更新日TO(YYYYMMDD)
+    #[arg(long)]
+    pub lst_date_to: Option<String>,
+    /// 提供館コード
+    #[arg(long)]
+    pub lib_id: Option<String>,
+    /// 図書館グループ(all, ndl, public, academic, special, school, archives)
+    #[arg(long)]
+    pub lib_group: Option<LibGroup>,
+    /// 館種コードまたはデコード値(--type profile の場合のみ)
+    #[arg(long)]
+    pub lib_type: Option<LibType>,
+    /// 取得する最大件数
+    #[arg(long, default_value_t = 1000)]
+    pub max_records: usize,
+    /// 出力形式
+    #[arg(long, value_enum, default_value = "csv")]
+    pub format: ExportFormat,
+    /// 出力先のファイル。指定がない場合は標準出力へ出力する。
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+impl From<&ExportArgs> for CrdSearchRequest {
+    fn from(value: &ExportArgs) -> Self {
+        CrdSearchRequest {
+            ty: value.ty,
+            condition: Condition {
+                query: value.query.clone(),
+                crt_date_from: value.crt_date_from.clone(),
+                crt_date_to: value.crt_date_to.clone(),
+                reg_date_from: value.reg_date_from.clone(),
+                reg_date_to: value.reg_date_to.clone(),
+                lst_date_from: value.lst_date_from.clone(),
+                lst_date_to: value.lst_date_to.clone(),
+            },
+            lib_id: value.lib_id.clone(),
+            lib_group: value.lib_group,
+            lib_type: value.lib_type.clone(),
+            results_get_position: None,
+            results_num: 100,
+        }
+    }
+}
+
+fn text(value: &str) -> String {
+    value.to_string()
+}
+
+fn opt(value: &Option<String>) -> String {
+    value.clone().unwrap_or_default()
+}
+
+/// 日時をISO 8601で出力する。読み取れない場合は元の文字列とする。
+fn date(value: Option<&CrdDateTime>) -> String {
+    value.map(ToString::to_string).unwrap_or_default()
+}
+
+/// 区分値を `resolved` などの出力と同じ表記にする。判定できない値は `unknown:<値>` とする。
+fn flag<T: Display>(value: &T) -> String {
+    value.to_string()
+}
+
+fn list<T: Display>(values: &Option<Vec<T>>) -> String {
+    values
+        .iter()
+        .flatten()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(LIST_SEPARATOR)
+}
+
+/// 検索結果1件を、項目名と値の組の一覧に平坦化する。
+///
+/// 項目は検索対象の種類ごとに固定の順序となる。
+pub fn flatten(result: &CrdSearchResult) -> Vec<(&'static str, String)> {
+    let mut row = match result {
+        CrdSearchResult::Reference {
+            url,
+            question,
+            registration_id,
+            answer,
+            created_at,
+            solution,
+            keywords,
+            classes,
+            survey_type,
+            content_type,
+            bibls,
+            answer_process,
+            referrals,
+            pre_survey,
+            note,
+            questioner_type,
+            contributors,
+            library: _,
+            system: _,
+        } => vec![
+            ("url", text(url)),
+            ("question", text(question)),
+            ("registration_id", text(registration_id)),
+            ("answer", text(answer)),
+            ("created_at", created_at.to_string()),
+            ("solution", flag(solution)),
+            ("keywords", list(keywords)),
+            ("classes", list(classes)),
+            ("survey_type", opt(survey_type)),
+            ("content_type", opt(content_type)),
+            ("bibls", list(bibls)),
+            ("answer_process", opt(answer_process)),
+            ("referrals", list(referrals)),
+            ("pre_survey", opt(pre_survey)),
+            ("note", opt(note)),
+            ("questioner_type", opt(questioner_type)),
+            ("contributors", list(contributors)),
+        ],
+        CrdSearchResult::Manual {
+            url,
+            theme,
+            registration_id,
+            guide,
+            created_at,
+            completion,
+            keywords,
+            classes,
+            bibls,
+            note,
+            library: _,
+            system: _,
+        } => vec![
+            ("url", text(url)),
+            ("theme", text(theme)),
+            ("registration_id", text(registration_id)),
+            ("guide", text(guide)),
+            ("created_at", created_at.to_string()),
+            ("completion", flag(completion)),
+            ("keywords", list(keywords)),
+            ("classes", list(classes)),
+            ("bibls", list(bibls)),
+            ("note", opt(note)),
+        ],
+        CrdSearchResult::Collection {
+            url,
+            name,
+            name_kana,
+            registration_id,
+            content,
+            origin,
+            restriction,
+            catalog,
+            literature,
+            number,
+            continuation,
+            keywords,
+            classes,
+            note,
+            library: _,
+            system: _,
+        } => vec![
+            ("url", text(url)),
+            ("name", text(name)),
+            ("name_kana", text(name_kana)),
+            ("registration_id", text(registration_id)),
+            ("content", text(content)),
+            ("origin", opt(origin)),
+            ("restriction", opt(restriction)),
+            ("catalog", opt(catalog)),
+            ("literature", opt(literature)),
+            ("number", opt(number)),
+            ("continuation", flag(continuation)),
+            ("keywords", list(keywords)),
+            ("classes", list(classes)),
+            ("note", opt(note)),
+        ],
+        CrdSearchResult::Profile {
+            url,
+            library_type,
+            library_name,
+            library_name_kana,
+            library_name_abbr,
+            zip_code,
+            address_prefecture,
+            address_city,
+            address_street,
+            tel1,
+            tel1_note,
+            tel2,
+            tel2_note,
+            tel3,
+            tel3_note,
+            fax,
+            e_mail,
+            homepage,
+            open_info,
+            restriction,
+            outline,
+            feature,
+            notes,
+            access,
+            isil,
+            system: _,
+        } => vec![
+            ("url", text(url)),
+            ("library_type", text(library_type.code())),
+            (
+                "library_type_label",
+                text(library_type.label().unwrap_or_default()),
+            ),
+            ("library_name", text(library_name)),
+            ("library_name_kana", text(library_name_kana)),
+            ("library_name_abbr", text(library_name_abbr)),
+            ("zip_code", text(zip_code)),
+            ("address_prefecture", text(address_prefecture)),
+            ("address_city", text(address_city)),
+            ("address_street", text(address_street)),
+            ("tel1", text(tel1)),
+            ("tel1_note", opt(tel1_note)),
+            ("tel2", opt(tel2)),
+            ("tel2_note", opt(tel2_note)),
+            ("tel3", opt(tel3)),
+            ("tel3_note", opt(tel3_note)),
+            ("fax", opt(fax)),
+            ("e_mail", opt(e_mail)),
+            ("homepage", opt(homepage)),
+            ("open_info", opt(open_info)),
+            ("restriction", opt(restriction)),
+            ("outline", opt(outline)),
+            ("feature", opt(feature)),
+            ("notes", opt(notes)),
+            ("access", opt(access)),
+            ("isil", opt(isil)),
+        ],
+        CrdSearchResult::Malformed {
+            ty: _,
+            url,
+            sys_id: _,
+            lib_id: _,
+            lib_name: _,
+            reg_date: _,
+            lst_date: _,
+            error,
+            raw_xml,
+        } => vec![
+            ("url", opt(url)),
+            ("error", text(error)),
+            ("raw_xml", text(raw_xml)),
+        ],
+    };
+    if let Some(sys_id) = result.sys_id() {
+        row.push(("sys_id", text(sys_id)));
+    }
+    row.extend([
+        ("lib_id", text(result.lib_id())),
+        ("lib_name", text(result.lib_name())),
+        ("reg_date", date(result.reg_date())),
+        ("lst_date", date(result.lst_date())),
+    ]);
+    row
+}
+
+/// タブ区切りで表現できない文字を空白に置き換える。
+fn sanitize_tsv(value: String) -> String {
+    value.replace(['\t', '\r', '\n'], " ")
+}
+
+fn render_table(results: &[&CrdSearchResult], format: ExportFormat) -> anyhow::Result<String> {
+    let mut builder = csv::WriterBuilder::new();
+    if format == ExportFormat::Tsv {
+        builder.delimiter(b'\t').quote_style(csv::QuoteStyle::Never);
+    }
+    let mut writer = builder.from_writer(Vec::new());
+    for (i, result) in results.iter().enumerate() {
+        let row = flatten(result);
+        if i == 0 {
+            writer.write_record(row.iter().map(|(column, _)| *column))?;
+        }
+        let values = row.into_iter().map(|(_, value)| match format {
+            ExportFormat::Tsv => sanitize_tsv(value),
+            _ => value,
+        });
+        writer.write_record(values)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn render_jsonl(results: &[CrdSearchResult]) -> anyhow::Result<String> {
+    let mut out = String::new();
+    for result in results {
+        let mut object = serde_json::Map::new();
+        object.insert("type".to_string(), result.kind().to_string().into());
+        for (column, value) in flatten(result) {
+            object.insert(column.to_string(), value.into());
+        }
+        out.push_str(&serde_json::to_string(&object)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// 検索結果を指定した形式に変換する。
+///
+/// CSV・TSVは検索対象の種類ごとに項目が異なるため、種類ごとに1つの出力とする。
+/// 読み込めなかったレコードは項目が揃わないため、CSV・TSVには出力しない。
+/// JSONLは `type` 項目を含めて1つの出力にまとめる。
+pub fn render(
+    results: &[CrdSearchResult],
+    format: ExportFormat,
+) -> anyhow::Result<Vec<(Option<ReqType>, String)>> {
+    if format == ExportFormat::Jsonl {
+        return Ok(vec![(None, render_jsonl(results)?)]);
+    }
+    let mut groups: Vec<(ReqType, Vec<&CrdSearchResult>)> = Vec::new();
+    for result in results
+        .iter()
+        .filter(|result| !matches!(result, CrdSearchResult::Malformed { .. }))
+    {
+        match groups.iter_mut().find(|(ty, _)| *ty == result.kind()) {
+            Some((_, group)) => group.push(result),
+            None => groups.push((result.kind(), vec![result])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(ty, group)| Ok((Some(ty), render_table(&group, format)?)))
+        .collect()
+}
+
+/// 出力をファイルへ書き込む。
+///
+/// 種類ごとに出力が分かれる場合は、`output.reference.csv` のように種類名を付けたファイルとする。
+pub fn write_files(
+    output: &Path,
+    format: ExportFormat,
+    rendered: Vec<(Option<ReqType>, String)>,
+) -> anyhow::Result<Vec<(Option<ReqType>, PathBuf)>> {
+    let split = rendered.len() > 1;
+    let mut paths = Vec::new();
+    for (ty, content) in rendered {
+        let path = match ty {
+            Some(ty) if split => output.with_extension(format!("{}.{}", ty, format.extension())),
+            _ => output.to_path_buf(),
+        };
+        let content = match format {
+            ExportFormat::Tsv => format!("\u{feff}{}", content),
+            _ => content,
+        };
+        std::fs::write(&path, content)?;
+        paths.push((ty, path));
+    }
+    Ok(paths)
+}
+
+/// 検索結果をページングしながら取得し、指定した形式で出力する。
+pub async fn export(
+    service: &CrdService,
+    request: CrdSearchRequest,
+    max_records: usize,
+    format: ExportFormat,
+    output: Option<&Path>,
+) -> anyhow::Result<ExportResponse> {
+    let response = service.search_all(request, max_records).await?;
+    let rendered = render(&response.results, format)?;
+    let files = match output {
+        Some(output) => write_files(output, format, rendered)?
+            .into_iter()
+            .map(|(ty, path)| ExportedFile {
+                ty,
+                path: Some(path.display().to_string()),
+                content: None,
+            })
+            .collect(),
+        None => rendered
+            .into_iter()
+            .map(|(ty, content)| ExportedFile {
+                ty,
+                path: None,
+                content: Some(content),
+            })
+            .collect(),
+    };
+    Ok(ExportResponse {
+        hit_count: response.hit_count,
+        records: response.results.len(),
+        files,
+        warnings: response.warnings,
+    })
+}
+
+/// 検索結果の参考資料を集め、重複を除いて指定した形式で出力する。
+pub async fn export_bibliography(
+    service: &CrdService,
+    request: CrdSearchRequest,
+    max_records: usize,
+    format: BibliographyFormat,
+) -> anyhow::Result<BibliographyResponse> {
+    let response = service.search_all(request, max_records).await?;
+    let entries = bibliography::collect(&response.results);
+    let content = bibliography::render(&entries, format)?;
+    Ok(BibliographyResponse {
+        records: response.results.len(),
+        entries: entries.len(),
+        content,
+    })
+}
+
+/// `export` サブコマンド
+pub async fn run(service: &CrdService, args: ExportArgs) -> anyhow::Result<()> {
+    let response = export(
+        service,
+        (&args).into(),
+        args.max_records,
+        args.format,
+        args.output.as_deref(),
+    )
+    .await?;
+    for warning in &response.warnings {
+        tracing::warn!("{}", warning);
+    }
+    for file in response.files {
+        match (file.path, file.content) {
+            (Some(path), _) => tracing::info!("wrote {}", path),
+            (None, Some(content)) => print!("{}", content),
+            (None, None) => {}
+        }
+    }
+    tracing::info!(
+        "exported {} of {} records",
+        response.records,
+        response.hit_count
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExportFormat, render};
+    use crate::crd::{CrdResultSet, NdcClass};
+    use crate::res::{ClassResult, CrdSearchResponse, CrdSearchResult};
+    use rmcp::ErrorData;
+
+    const REFERENCE_XML: &str = include_str!("fixtures/reference.xml");
+
+    fn results() -> Vec<CrdSearchResult> {
+        let set = CrdResultSet::parse(REFERENCE_XML).unwrap();
+        Result::<CrdSearchResponse, ErrorData>::from(set)
+            .unwrap()
+            .results
+    }
+
+    #[test]
+    fn test_render_csv() {
+        let rendered = render(&results(), ExportFormat::Csv).unwrap();
+        assert_eq!(rendered.len(), 1);
+        let mut lines = rendered[0].1.lines();
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .starts_with("url,question,registration_id,")
+        );
+        let first = lines.next().unwrap();
+        assert!(first.contains(",北海道; 開拓使,NDC9:211,"));
+        assert!(first.contains("ISBN:4-8328-0001-5"));
+    }
+
+    #[test]
+    fn test_render_jsonl() {
+        let rendered = render(&results(), ExportFormat::Jsonl).unwrap();
+        let lines = rendered[0].1.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let row: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(row["type"], "reference");
+        assert_eq!(row["classes"], "NDC10:291.1");
+        assert_eq!(row["sys_id"], "1000300002");
+    }
+
+    #[test]
+    fn test_render_unknown_flag() {
+        // 判定できない区分値も出力する
+        let xml = REFERENCE_XML.replacen("<solution>0</solution>", "<solution>保留</solution>", 1);
+        let set = CrdResultSet::parse(&xml).unwrap();
+        let results = Result::<CrdSearchResponse, ErrorData>::from(set)
+            .unwrap()
+            .results;
+        let rendered = render(&results, ExportFormat::Tsv).unwrap();
+        let first = rendered[0].1.lines().nth(1).unwrap();
+        assert!(first.split('\t').any(|cell| cell == "unknown:保留"));
+
+        let rendered = render(&results, ExportFormat::Jsonl).unwrap();
+        let row: serde_json::Value =
+            serde_json::from_str(rendered[0].1.lines().next().unwrap()).unwrap();
+        assert_eq!(row["solution"], "unknown:保留");
+        assert_eq!(
+            serde_json::to_value(&results[1]).unwrap()["Reference"]["solution"],
+            "unresolved"
+        );
+    }
+
+    #[test]
+    fn test_ndc_display() {
+        let class: NdcClass =
+            quick_xml::de::from_str(r#"<classes type="NDC" code="9">123.4</classes>"#).unwrap();
+        assert_eq!(ClassResult::from(class).to_string(), "NDC9:123.4");
+    }
+}
@@ -0,0 +1,371 @@
+use crate::res::{BiblResult, CrdSearchResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use unicode_normalization::UnicodeNormalization;
+
+/// 参考文献の出力形式
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BibliographyFormat {
+    /// RIS
+    Ris,
+    /// BibTeX
+    Bibtex,
+    /// CSL-JSON
+    CslJson,
+}
+
+/// 重複を除いた参考資料
+#[derive(Debug, Clone)]
+pub struct BibliographyEntry {
+    /// 書誌的事項
+    pub desc: Option<String>,
+    /// ISBN
+    pub isbn: Option<String>,
+    /// ISBN-13に正規化したISBN
+    pub isbn13: Option<String>,
+    /// 備考
+    pub note: Option<String>,
+    /// この資料を参考資料とするレコードの登録番号
+    pub cited_by: Vec<String>,
+}
+
+/// ISBN-13に正規化できなかったISBNの重複判定のための値。数字とチェックディジットのXのみを残す。
+fn isbn_key(isbn: &str) -> Option<String> {
+    let key = isbn
+        .nfkc()
+        .filter(|c| c.is_ascii_digit() || *c == 'X' || *c == 'x')
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>();
+    (!key.is_empty()).then_some(key)
+}
+
+/// 重複判定のための書誌的事項。全角・半角や記号、空白の違いを無視する。
+fn desc_key(desc: &str) -> Option<String> {
+    let key = desc
+        .nfkc()
+        .flat_map(char::to_lowercase)
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>();
+    (!key.is_empty()).then_some(key)
+}
+
+impl BibliographyEntry {
+    fn matches(&self, bibl: &BiblResult) -> bool {
+        let isbn = |isbn13: &Option<String>, isbn: &Option<String>| {
+            isbn13
+                .clone()
+                .or_else(|| isbn.as_deref().and_then(isbn_key))
+        };
+        let desc = |desc: &Option<String>| desc.as_deref().and_then(desc_key);
+        match (
+            isbn(&self.isbn13, &self.isbn),
+            isbn(&bibl.isbn13, &bibl.isbn),
+        ) {
+            (Some(a), Some(b)) => a == b,
+            _ => desc(&self.desc).is_some() && desc(&self.desc) == desc(&bibl.desc),
+        }
+    }
+
+    /// 書誌的事項の『』または「」で囲まれた部分を書名とする。
+    pub fn title(&self) -> Option<String> {
+        let desc = self.desc.as_deref()?;
+        for (open, close) in [('『', '』'), ('「', '」')] {
+            if let Some(start) = desc.find(open) {
+                let rest = &desc[start + open.len_utf8()..];
+                if let Some(end) = rest.find(close) {
+                    return Some(rest[..end].trim().to_string());
+                }
+            }
+        }
+        Some(desc.trim().to_string())
+    }
+
+    /// 書誌的事項に含まれる4桁の数字のうち、最後のものを出版年とする。
+    pub fn year(&self) -> Option<u16> {
+        let desc = self.desc.as_deref()?.nfkc().collect::<String>();
+        let chars = desc.chars().collect::<Vec<_>>();
+        let mut year = None;
+        let mut i = 0;
+        while i < chars.len() {
+            let len = chars[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+            if len == 4 {
+                let value = chars[i..i + 4]
+                    .iter()
+                    .collect::<String>()
+                    .parse::<u16>()
+                    .ok();
+                if let Some(value) = value.filter(|y| (1600..=2100).contains(y)) {
+                    year = Some(value);
+                }
+            }
+            i += len.max(1);
+        }
+        year
+    }
+
+    /// 出力するISBN。ISBN-13に正規化できた場合は正規化した値とする。
+    fn isbn(&self) -> Option<&str> {
+        self.isbn13.as_deref().or(self.isbn.as_deref())
+    }
+
+    /// 引用キー
+    fn key(&self, index: usize) -> String {
+        match self.isbn.as_deref().and_then(isbn_key) {
+            Some(isbn) => format!("isbn{}", isbn),
+            None => format!("crd{}", index + 1),
+        }
+    }
+
+    /// 出典として記録する備考
+    fn notes(&self) -> Vec<String> {
+        let mut notes = Vec::new();
+        if let Some(desc) = &self.desc {
+            notes.push(desc.clone());
+        }
+        if let Some(note) = &self.note {
+            notes.push(note.clone());
+        }
+        if !self.cited_by.is_empty() {
+            notes.push(format!("CRD登録番号: {}", self.cited_by.join(", ")));
+        }
+        notes
+    }
+}
+
+/// 検索結果に含まれる参考資料を、ISBNまたは正規化した書誌的事項で重複を除いて集める。
+pub fn collect(results: &[CrdSearchResult]) -> Vec<BibliographyEntry> {
+    let mut entries: Vec<BibliographyEntry> = Vec::new();
+    for result in results {
+        for bibl in result.bibls() {
+            if bibl.desc.is_none() && bibl.isbn.is_none() {
+                continue;
+            }
+            let entry = match entries.iter_mut().find(|e| e.matches(bibl)) {
+                Some(entry) => entry,
+                None => {
+                    entries.push(BibliographyEntry {
+                        desc: None,
+                        isbn: None,
+                        isbn13: None,
+                        note: None,
+                        cited_by: Vec::new(),
+                    });
+                    entries.last_mut().unwrap()
+                }
+            };
+            entry.desc = entry.desc.take().or_else(|| bibl.desc.clone());
+            entry.isbn = entry.isbn.take().or_else(|| bibl.isbn.clone());
+            entry.isbn13 = entry.isbn13.take().or_else(|| bibl.isbn13.clone());
+            entry.note = entry.note.take().or_else(|| bibl.note.clone());
+            if let Some(sys_id) = result.sys_id()
+                && !entry.cited_by.iter().any(|s| s == sys_id)
+            {
+                entry.cited_by.push(sys_id.to_string());
+            }
+        }
+    }
+    entries
+}
+
+fn single_line(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn to_ris(entries: &[BibliographyEntry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        let ty = if entry.isbn.is_some() { "BOOK" } else { "GEN" };
+        out.push_str(&format!("TY  - {}\r\n", ty));
+        if let Some(title) = entry.title() {
+            out.push_str(&format!("TI  - {}\r\n", single_line(&title)));
+        }
+        if let Some(year) = entry.year() {
+            out.push_str(&format!("PY  - {}\r\n", year));
+        }
+        if let Some(isbn) = entry.isbn() {
+            out.push_str(&format!("SN  - {}\r\n", single_line(isbn)));
+        }
+        for note in entry.notes() {
+            out.push_str(&format!("N1  - {}\r\n", single_line(&note)));
+        }
+        out.push_str("ER  - \r\n\r\n");
+    }
+    out
+}
+
+fn escape_bibtex(value: &str) -> String {
+    let mut out = String::new();
+    for c in single_line(value).chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '{' | '}' | '%' | '&' | '#' | '$' | '_' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn to_bibtex(entries: &[BibliographyEntry]) -> String {
+    let mut out = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let ty = if entry.isbn.is_some() { "book" } else { "misc" };
+        out.push_str(&format!("@{}{{{},\n", ty, entry.key(i)));
+        if let Some(title) = entry.title() {
+            out.push_str(&format!("  title = {{{}}},\n", escape_bibtex(&title)));
+        }
+        if let Some(year) = entry.year() {
+            out.push_str(&format!("  year = {{{}}},\n", year));
+        }
+        if let Some(isbn) = entry.isbn() {
+            out.push_str(&format!("  isbn = {{{}}},\n", escape_bibtex(isbn)));
+        }
+        let notes = entry.notes();
+        if !notes.is_empty() {
+            out.push_str(&format!(
+                "  note = {{{}}},\n",
+                escape_bibtex(&notes.join(". "))
+            ));
+        }
+        out.push_str("}\n\n");
+    }
+    out
+}
+
+fn to_csl_json(entries: &[BibliographyEntry]) -> anyhow::Result<String> {
+    let items = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let mut item = json!({
+                "id": entry.key(i),
+                "type": if entry.isbn.is_some() { "book" } else { "document" },
+            });
+            if let Some(title) = entry.title() {
+                item["title"] = title.into();
+            }
+            if let Some(year) = entry.year() {
+                item["issued"] = json!({ "date-parts": [[year]] });
+            }
+            if let Some(isbn) = entry.isbn() {
+                item["ISBN"] = isbn.into();
+            }
+            let notes = entry.notes();
+            if !notes.is_empty() {
+                item["note"] = notes.join("\n").into();
+            }
+            item
+        })
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string_pretty(&items)?)
+}
+
+/// 参考資料を指定した形式に変換する。
+pub fn render(entries: &[BibliographyEntry], format: BibliographyFormat) -> anyhow::Result<String> {
+    match format {
+        BibliographyFormat::Ris => Ok(to_ris(entries)),
+        BibliographyFormat::Bibtex => Ok(to_bibtex(entries)),
+        BibliographyFormat::CslJson => to_csl_json(entries),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BibliographyFormat, collect, escape_bibtex, render};
+    use crate::crd::{Bibl, Completion};
+    use crate::date::CrdDate;
+    use crate::res::{BiblResult, CrdSearchResult};
+
+    fn bibl(desc: &str, isbn: Option<&str>) -> BiblResult {
+        Bibl {
+            desc: Some(desc.to_string()),
+            isbn: isbn.map(str::to_string),
+            note: None,
+        }
+        .into()
+    }
+
+    fn manual(sys_id: &str, bibls: Vec<BiblResult>) -> CrdSearchResult {
+        let system = serde_json::json!({
+            "reg-date": "", "lst-date": "", "sys-id": sys_id,
+            "lib-id": "", "lib-name": "", "file-num": 0,
+        });
+        CrdSearchResult::Manual {
+            url: String::new(),
+            theme: String::new(),
+            registration_id: String::new(),
+            guide: String::new(),
+            created_at: CrdDate::default(),
+            completion: Completion::Completed,
+            keywords: None,
+            classes: None,
+            bibls: Some(bibls),
+            note: None,
+            library: None,
+            system: serde_json::from_value(system).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_collect() {
+        let results = vec![
+            manual(
+                "1",
+                vec![
+                    bibl("『新北海道史 第3巻』 北海道 1971", Some("4-00-310101-4")),
+                    bibl("『北海道の地名』 平凡社 2003", None),
+                ],
+            ),
+            manual(
+                "2",
+                vec![
+                    bibl("新北海道史 3", Some("ISBN978-4-00-310101-8")),
+                    bibl("『北海道の地名』　平凡社　２００３", None),
+                ],
+            ),
+        ];
+        let entries = collect(&results);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].cited_by, vec!["1", "2"]);
+        assert_eq!(entries[0].title().unwrap(), "新北海道史 第3巻");
+        assert_eq!(entries[0].year(), Some(1971));
+        assert_eq!(entries[1].cited_by, vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_render() {
+        let entries = collect(&[manual(
+            "1",
+            vec![bibl(
+                "『新北海道史 第3巻』 北海道 1971",
+                Some("4-00-310101-4"),
+            )],
+        )]);
+        let ris = render(&entries, BibliographyFormat::Ris).unwrap();
+        assert!(ris.starts_with("TY  - BOOK\r\nTI  - 新北海道史 第3巻\r\nPY  - 1971\r\n"));
+        assert!(ris.ends_with("ER  - \r\n\r\n"));
+
+        let bibtex = render(&entries, BibliographyFormat::Bibtex).unwrap();
+        assert!(bibtex.starts_with("@book{isbn4003101014,\n  title = {新北海道史 第3巻},\n"));
+
+        let csl = render(&entries, BibliographyFormat::CslJson).unwrap();
+        let csl: serde_json::Value = serde_json::from_str(&csl).unwrap();
+        assert_eq!(csl[0]["issued"]["date-parts"][0][0], 1971);
+        assert_eq!(csl[0]["ISBN"], "9784003101018");
+        assert!(ris.contains("SN  - 9784003101018\r\n"));
+    }
+
+    #[test]
+    fn test_escape_bibtex() {
+        assert_eq!(
+            escape_bibtex(r"C:\data ~ x^2 {50%}"),
+            r"C:\textbackslash{}data \textasciitilde{} x\textasciicircum{}2 \{50\%\}"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use crate::query::table_lines;
+use std::fmt::{Display, Formatter};
+use std::sync::LazyLock;
+use unicode_normalization::UnicodeNormalization;
+
+/// 出版者記号の範囲
+const RANGES_TABLE: &str = include_str!("isbn/ranges.tsv");
+
+/// 接頭辞・登録グループと、出版者記号の範囲(先頭・末尾)
+static RANGES: LazyLock<Vec<[&str; 4]>> = LazyLock::new(|| {
+    table_lines(RANGES_TABLE)
+        .filter_map(|fields| <[&str; 4]>::try_from(fields).ok())
+        .filter(|[_, _, first, last]| first.len() == last.len())
+        .collect()
+});
+
+/// ISBN-13に正規化したISBN
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Isbn {
+    digits: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IsbnError {
+    /// ISBNとみなせる数字列が含まれていない
+    NotFound,
+    /// 桁数が10桁・13桁のいずれでもない
+    InvalidLength(usize),
+    /// チェックディジット以外の桁に数字でない文字がある
+    InvalidFormat,
+    /// チェックディジットが一致しない
+    InvalidCheckDigit,
+    /// ISBN-13の接頭辞が978・979でない
+    InvalidPrefix,
+}
+
+impl Display for IsbnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IsbnError::NotFound => write!(f, "ISBNが含まれていません"),
+            IsbnError::InvalidLength(len) => {
+                write!(f, "ISBNの桁数が正しくありません({}桁)", len)
+            }
+            IsbnError::InvalidFormat => {
+                write!(
+                    f,
+                    "ISBNの形式が正しくありません(Xはチェックディジットにのみ使えます)"
+                )
+            }
+            IsbnError::InvalidCheckDigit => write!(f, "ISBNのチェックディジットが正しくありません"),
+            IsbnError::InvalidPrefix => write!(f, "ISBN-13の接頭辞は978または979です"),
+        }
+    }
+}
+
+impl std::error::Error for IsbnError {}
+
+fn isbn10_check_digit(digits: &[u32]) -> char {
+    let sum: u32 = digits
+        .iter()
+        .take(9)
+        .enumerate()
+        .map(|(i, d)| d * (10 - i as u32))
+        .sum();
+    match (11 - sum % 11) % 11 {
+        10 => 'X',
+        d => char::from_digit(d, 10).unwrap(),
+    }
+}
+
+fn isbn13_check_digit(digits: &[u32]) -> char {
+    let sum: u32 = digits
+        .iter()
+        .take(12)
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { *d } else { d * 3 })
+        .sum();
+    char::from_digit((10 - sum % 10) % 10, 10).unwrap()
+}
+
+impl Isbn {
+    /// 文字列からISBNを読み取る。
+    ///
+    /// ハイフンの有無や全角文字、`ISBN` の接頭辞や後続の注記(`(上巻)` など)を許容し、
+    /// 最初に現れる数字列をISBN-10またはISBN-13として検証する。
+    pub fn parse(value: &str) -> Result<Isbn, IsbnError> {
+        let normalized = value.nfkc().collect::<String>().to_ascii_uppercase();
+        let normalized = normalized.replace("ISBN-13", "").replace("ISBN-10", "");
+        let candidate = normalized
+            .split(|c: char| !(c.is_ascii_digit() || c == 'X' || c == '-'))
+            .map(|s| s.replace('-', ""))
+            .find(|s| s.chars().any(|c| c.is_ascii_digit()))
+            .ok_or(IsbnError::NotFound)?;
+
+        match candidate.len() {
+            10 => {
+                let (body, check) = candidate.split_at(9);
+                if !body.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(IsbnError::InvalidFormat);
+                }
+                let digits = body
+                    .chars()
+                    .filter_map(|c| c.to_digit(10))
+                    .collect::<Vec<_>>();
+                if !check.starts_with(isbn10_check_digit(&digits)) {
+                    return Err(IsbnError::InvalidCheckDigit);
+                }
+                let mut digits13 = vec![9, 7, 8];
+                digits13.extend(digits);
+                let mut isbn = digits13
+                    .iter()
+                    .map(|d| char::from_digit(*d, 10).unwrap())
+                    .collect::<String>();
+                isbn.push(isbn13_check_digit(&digits13));
+                Ok(Isbn { digits: isbn })
+            }
+            13 => {
+                if !candidate.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(IsbnError::InvalidFormat);
+                }
+                if !(candidate.starts_with("978") || candidate.starts_with("979")) {
+                    return Err(IsbnError::InvalidPrefix);
+                }
+                let digits = candidate
+                    .chars()
+                    .filter_map(|c| c.to_digit(10))
+                    .collect::<Vec<_>>();
+                if !candidate.ends_with(isbn13_check_digit(&digits)) {
+                    return Err(IsbnError::InvalidCheckDigit);
+                }
+                Ok(Isbn { digits: candidate })
+            }
+            len => Err(IsbnError::InvalidLength(len)),
+        }
+    }
+
+    /// ハイフンなしのISBN-13
+    pub fn isbn13(&self) -> &str {
+        &self.digits
+    }
+
+    /// ハイフンなしのISBN-10。接頭辞が979のISBNには存在しない。
+    pub fn isbn10(&self) -> Option<String> {
+        let body = self.digits.strip_prefix("978")?;
+        let digits = body
+            .chars()
+            .take(9)
+            .filter_map(|c| c.to_digit(10))
+            .collect::<Vec<_>>();
+        let mut isbn = body[..9].to_string();
+        isbn.push(isbn10_check_digit(&digits));
+        Some(isbn)
+    }
+
+    /// ハイフンで区切ったISBN-13とISBN-10。
+    ///
+    /// 区切り位置は出版者記号の範囲表から決める。範囲表にない登録グループの場合は `None` を返す。
+    pub fn hyphenated(&self) -> Option<(String, Option<String>)> {
+        let [prefix, group, first, _] = RANGES.iter().find(|[prefix, group, first, last]| {
+            let start = prefix.len() + group.len();
+            self.digits.starts_with(&format!("{}{}", prefix, group))
+                && self
+                    .digits
+                    .get(start..start + first.len())
+                    .is_some_and(|registrant| (*first..=*last).contains(&registrant))
+        })?;
+        let start = prefix.len() + group.len();
+        let (registrant, rest) = self.digits[start..12].split_at(first.len());
+        let check = &self.digits[12..];
+        let isbn13 = format!("{}-{}-{}-{}-{}", prefix, group, registrant, rest, check);
+        let isbn10 = self
+            .isbn10()
+            .map(|isbn10| format!("{}-{}-{}-{}", group, registrant, rest, &isbn10[9..]));
+        Some((isbn13, isbn10))
+    }
+
+    /// ISBN-10・ISBN-13のどちらで登録された参考資料にも一致する `bibl-isbn` の検索句
+    ///
+    /// CRDには参考資料のISBNが登録時の表記のまま収録されているため、ハイフンなしの表記に加え、
+    /// 出版者記号の範囲表から区切り位置がわかる場合はハイフンで区切った表記も検索語とする。
+    pub fn cql_clause(&self) -> String {
+        let mut forms = vec![self.isbn13().to_string()];
+        forms.extend(self.isbn10());
+        if let Some((isbn13, isbn10)) = self.hyphenated() {
+            forms.push(isbn13);
+            forms.extend(isbn10);
+        }
+        format!("bibl-isbn any \"{}\"", forms.join(" "))
+    }
+}
+
+impl Display for Isbn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Isbn, IsbnError};
+
+    #[test]
+    fn test_parse() {
+        let isbn = Isbn::parse("4-00-310101-4").unwrap();
+        assert_eq!(isbn.isbn13(), "9784003101018");
+        assert_eq!(isbn.isbn10().unwrap(), "4003101014");
+
+        assert_eq!(Isbn::parse("978-4-00-310101-8").unwrap(), isbn);
+        assert_eq!(
+            Isbn::parse("ISBN ４００３１０１０１４ (上巻)").unwrap(),
+            isbn
+        );
+        assert_eq!(Isbn::parse("ISBN-13: 9784003101018").unwrap(), isbn);
+
+        assert_eq!(
+            Isbn::parse("4-00-000008-x").unwrap().isbn10().unwrap(),
+            "400000008X"
+        );
+        assert_eq!(Isbn::parse("979-10-90636-07-1").unwrap().isbn10(), None);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(
+            Isbn::parse("4-00-310101-5"),
+            Err(IsbnError::InvalidCheckDigit)
+        );
+        assert_eq!(Isbn::parse("4-0X-310101-4"), Err(IsbnError::InvalidFormat));
+        assert_eq!(Isbn::parse("123-4"), Err(IsbnError::InvalidLength(4)));
+        assert_eq!(Isbn::parse("なし"), Err(IsbnError::NotFound));
+        assert_eq!(Isbn::parse("1234567890128"), Err(IsbnError::InvalidPrefix));
+    }
+
+    #[test]
+    fn test_cql_clause() {
+        let isbn = Isbn::parse("978-4-00-310101-8").unwrap();
+        assert_eq!(
+            isbn.cql_clause(),
+            "bibl-isbn any \"9784003101018 4003101014 978-4-00-310101-8 4-00-310101-4\""
+        );
+        assert_eq!(
+            Isbn::parse("4-87311-565-5").unwrap().hyphenated(),
+            Some((
+                "978-4-87311-565-8".to_string(),
+                Some("4-87311-565-5".to_string())
+            ))
+        );
+        assert_eq!(
+            Isbn::parse("979-10-90636-07-1").unwrap().cql_clause(),
+            "bibl-isbn any \"9791090636071\""
+        );
+    }
+}
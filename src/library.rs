@@ -0,0 +1,466 @@
+use crate::req::{Condition, CrdSearchRequest, IsilRequest, LibGroup, ReqType};
+use crate::res::{CrdSearchResponse, CrdSearchResult, IsilCheck, IsilResponse, LibraryEntry};
+use crate::service::{CrdService, check_max_records};
+use rmcp::ErrorData;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use unicode_normalization::UnicodeNormalization;
+
+/// 一度の検索でまとめて参加館プロファイルを取得する館数
+const LIBRARY_BATCH_SIZE: usize = 20;
+/// 全ての参加館を対象とするための登録日の下限
+pub const LIST_SINCE: &str = "19000101";
+
+/// 都道府県名
+pub const PREFECTURES: [&str; 47] = [
+    "北海道",
+    "青森県",
+    "岩手県",
+    "宮城県",
+    "秋田県",
+    "山形県",
+    "福島県",
+    "茨城県",
+    "栃木県",
+    "群馬県",
+    "埼玉県",
+    "千葉県",
+    "東京都",
+    "神奈川県",
+    "新潟県",
+    "富山県",
+    "石川県",
+    "福井県",
+    "山梨県",
+    "長野県",
+    "岐阜県",
+    "静岡県",
+    "愛知県",
+    "三重県",
+    "滋賀県",
+    "京都府",
+    "大阪府",
+    "兵庫県",
+    "奈良県",
+    "和歌山県",
+    "鳥取県",
+    "島根県",
+    "岡山県",
+    "広島県",
+    "山口県",
+    "徳島県",
+    "香川県",
+    "愛媛県",
+    "高知県",
+    "福岡県",
+    "佐賀県",
+    "長崎県",
+    "熊本県",
+    "大分県",
+    "宮崎県",
+    "鹿児島県",
+    "沖縄県",
+];
+
+/// 都道府県名を正式な表記にする。`東京` のように「都」「府」「県」を省いた名前も受け付ける。
+pub fn normalize_prefecture(value: &str) -> Option<&'static str> {
+    let value = value.trim();
+    PREFECTURES.iter().copied().find(|pref| {
+        *pref == value
+            || pref
+                .strip_suffix(['都', '府', '県'])
+                .is_some_and(|name| name == value)
+    })
+}
+
+/// CQLの検索語として使えるよう、引用符を除いて `"` で囲む。
+pub fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', " ").trim())
+}
+
+/// ISILの最大文字数(ISO 15511)
+const ISIL_MAX_LEN: usize = 16;
+
+/// ISILの形式を検証し、接頭辞を大文字にした表記を返す。
+///
+/// ISO 15511の `接頭辞-館識別子` の形式で、全体で16文字以内とする。
+/// 日本の図書館(接頭辞 `JP`)の館識別子は7桁の数字とする。
+pub fn normalize_isil(value: &str) -> Result<String, String> {
+    let value = value
+        .nfkc()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    let Some((prefix, identifier)) = value.split_once('-') else {
+        return Err(format!("ISILは「接頭辞-館識別子」の形式です: {}", value));
+    };
+    let prefix = prefix.to_ascii_uppercase();
+    if prefix.is_empty() || prefix.len() > 4 || !prefix.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("ISILの接頭辞が正しくありません: {}", value));
+    }
+    if identifier.is_empty()
+        || !identifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '/' | ':'))
+    {
+        return Err(format!("ISILの館識別子が正しくありません: {}", value));
+    }
+    if value.chars().count() > ISIL_MAX_LEN {
+        return Err(format!("ISILは{}文字以内です: {}", ISIL_MAX_LEN, value));
+    }
+    if prefix == "JP" && !(identifier.len() == 7 && identifier.chars().all(|c| c.is_ascii_digit()))
+    {
+        return Err(format!("日本のISILの館識別子は7桁の数字です: {}", value));
+    }
+    Ok(format!("{}-{}", prefix, identifier))
+}
+
+/// ISILで参加館を検索し、参加館プロファイルの連絡先を返す。
+pub async fn find_by_isil(
+    service: &CrdService,
+    isil: &str,
+) -> Result<Vec<LibraryEntry>, ErrorData> {
+    let request = profile_request(Some(format!("isil = {}", quote(isil))), None);
+    let response = service.search_all(request, 10).await?;
+    Ok(response
+        .results
+        .iter()
+        .filter_map(LibraryEntry::new)
+        .filter(|entry| entry.isil.as_deref().map(str::trim) == Some(isil))
+        .collect())
+}
+
+/// 図書館コードで参加館プロファイルを検索する。
+pub async fn find_by_lib_id(
+    service: &CrdService,
+    lib_id: &str,
+) -> Result<Vec<LibraryEntry>, ErrorData> {
+    let request = profile_request(None, Some(lib_id.to_string()));
+    let response = service.search_all(request, 1).await?;
+    Ok(response
+        .results
+        .iter()
+        .filter_map(LibraryEntry::new)
+        .collect())
+}
+
+/// 所在地の都道府県で参加館プロファイルを検索し、その都道府県の参加館を返す。
+pub async fn libraries_in_prefecture(
+    service: &CrdService,
+    prefecture: &str,
+) -> Result<Vec<LibraryEntry>, ErrorData> {
+    let normalized = normalize_prefecture(prefecture).ok_or_else(|| {
+        ErrorData::invalid_params(
+            format!("都道府県名が正しくありません: {}", prefecture),
+            None,
+        )
+    })?;
+    let request = profile_request(Some(format!("address any {}", quote(normalized))), None);
+    let response = service.search_all(request, 2000).await?;
+    Ok(response
+        .results
+        .iter()
+        .filter_map(LibraryEntry::new)
+        .filter(|entry| entry.address.contains(normalized))
+        .collect())
+}
+
+/// 検索条件に、提供館を `libraries` に限る検索句を加えて検索する。
+///
+/// 図書館名をまとめて `lib-name` で検索し、図書館コードの一致する事例のみを残す。ヒット件数と
+/// `results_returned` は図書館名の検索でヒット・取得した件数の合計のため、同じ名前を含む他の館の事例を含むことがある。
+/// 全体で `max_records` 件を上限とする。
+pub async fn search_in_libraries(
+    service: &CrdService,
+    request: CrdSearchRequest,
+    libraries: &[LibraryEntry],
+    max_records: usize,
+) -> Result<CrdSearchResponse, ErrorData> {
+    let mut hit_count = 0;
+    let mut results = Vec::new();
+    let mut warnings = Vec::new();
+    let mut fetched = 0;
+    for chunk in libraries.chunks(LIBRARY_BATCH_SIZE) {
+        if fetched >= max_records {
+            break;
+        }
+        let names = chunk
+            .iter()
+            .map(|entry| entry.name.replace(['"', ' ', '　'], ""))
+            .collect::<Vec<_>>();
+        let clause = format!("lib-name any {}", quote(&names.join(" ")));
+        let mut request = request.clone();
+        request.condition.query = Some(match request.condition.query.take() {
+            Some(query) => format!("{} and {}", query, clause),
+            None => clause,
+        });
+        let response = service.search_all(request, max_records - fetched).await?;
+        hit_count += response.hit_count;
+        fetched += response.results.len();
+        warnings.extend(response.warnings);
+        results.extend(
+            response
+                .results
+                .into_iter()
+                .filter(|result| chunk.iter().any(|entry| entry.lib_id == result.lib_id())),
+        );
+    }
+    Ok(CrdSearchResponse {
+        hit_count,
+        cursor_position: request.results_get_position.unwrap_or(0),
+        results_returned: fetched as i32,
+        has_more: (fetched as i32) < hit_count,
+        next_cursor: None,
+        results,
+        warnings,
+        sort: None,
+        sections: Vec::new(),
+        expanded_query: None,
+        relaxation: None,
+    })
+}
+
+/// 図書館グループに含まれる参加館を館種ごとに検索し、`max_records` 件を上限として返す。
+pub async fn list_group(
+    service: &CrdService,
+    group: LibGroup,
+    max_records: usize,
+) -> Result<(i32, Vec<LibraryEntry>), ErrorData> {
+    let mut hit_count = 0;
+    let mut libraries = Vec::new();
+    for lib_type in group.lib_types() {
+        if libraries.len() >= max_records {
+            break;
+        }
+        let mut request = profile_request(None, None);
+        request.lib_type = Some(lib_type);
+        let response = service
+            .search_all(request, max_records - libraries.len())
+            .await?;
+        hit_count += response.hit_count;
+        libraries.extend(response.results.iter().filter_map(LibraryEntry::new));
+    }
+    Ok((hit_count, libraries))
+}
+
+/// ISILの検証と参加館の照会を行う。
+///
+/// isilを指定した場合は形式を検証し、正しい場合のみ参加館を照会する。lib_idを指定した場合は参加館のISILを、
+/// lib_groupを指定した場合はグループに含まれる参加館とISILの一覧を返す。
+pub async fn lookup_isil(
+    service: &CrdService,
+    request: IsilRequest,
+) -> Result<IsilResponse, ErrorData> {
+    let IsilRequest {
+        isil,
+        lib_id,
+        lib_group,
+        max_records,
+    } = request;
+    match (isil, lib_id, lib_group) {
+        (Some(isil), None, None) => {
+            let (check, libraries) = match normalize_isil(&isil) {
+                Ok(normalized) => {
+                    let libraries = find_by_isil(service, &normalized).await?;
+                    let check = IsilCheck {
+                        value: isil,
+                        valid: true,
+                        normalized: Some(normalized),
+                        error: None,
+                    };
+                    (check, libraries)
+                }
+                Err(error) => {
+                    let check = IsilCheck {
+                        value: isil,
+                        valid: false,
+                        normalized: None,
+                        error: Some(error),
+                    };
+                    (check, Vec::new())
+                }
+            };
+            Ok(IsilResponse {
+                check: Some(check),
+                hit_count: libraries.len() as i32,
+                libraries,
+            })
+        }
+        (None, Some(lib_id), None) => {
+            let libraries = find_by_lib_id(service, &lib_id).await?;
+            Ok(IsilResponse {
+                check: None,
+                hit_count: libraries.len() as i32,
+                libraries,
+            })
+        }
+        (None, None, Some(lib_group)) => {
+            let max_records = check_max_records(max_records, 10000)?;
+            let (hit_count, libraries) = list_group(service, lib_group, max_records).await?;
+            Ok(IsilResponse {
+                check: None,
+                hit_count,
+                libraries,
+            })
+        }
+        _ => Err(ErrorData::invalid_params(
+            "isil, lib_id, lib_group のいずれか1つを指定してください",
+            None,
+        )),
+    }
+}
+
+/// 図書館コードごとの参加館の連絡先のキャッシュ
+///
+/// 参加館プロファイルが見つからなかった図書館コードも `None` として記録し、再検索しない。
+#[derive(Debug, Clone, Default)]
+pub struct LibraryCache(Arc<Mutex<HashMap<String, Option<LibraryEntry>>>>);
+
+/// 図書館コードの補完の候補。図書館コード・図書館名（正式）・図書館名（略式）を返す。
+///
+/// 入力のたびに呼ばれるため、CRDは検索せず、キャッシュとローカルミラーの参加館のみを候補とする。
+/// ローカルミラーの参加館は初回に読み込んで保持し、読み込めない場合は候補に含めない。
+pub async fn library_candidates(service: &CrdService) -> Vec<(String, String, String)> {
+    let mut libraries: Vec<(String, String, String)> = service
+        .library_cache
+        .0
+        .lock()
+        .await
+        .values()
+        .flatten()
+        .map(|entry| (entry.lib_id.clone(), entry.name.clone(), entry.abbr.clone()))
+        .collect();
+    if service
+        .mirror_path
+        .as_ref()
+        .is_some_and(|path| path.exists())
+    {
+        let mirrored = service
+            .mirror_libraries
+            .get_or_try_init(|| service.with_mirror_db(|mirror| mirror.libraries()))
+            .await;
+        match mirrored {
+            Ok(mirrored) => {
+                for library in mirrored {
+                    if !libraries.iter().any(|(lib_id, _, _)| *lib_id == library.0) {
+                        libraries.push(library.clone());
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("failed to load libraries from the mirror: {}", e.message),
+        }
+    }
+    libraries
+}
+
+/// 参加館プロファイルの検索条件。検索句がない場合は登録日の下限を指定し、全ての参加館を対象とする。
+fn profile_request(query: Option<String>, lib_id: Option<String>) -> CrdSearchRequest {
+    CrdSearchRequest {
+        ty: ReqType::Profile,
+        condition: Condition {
+            reg_date_from: query.is_none().then(|| LIST_SINCE.to_string()),
+            query,
+            crt_date_from: None,
+            crt_date_to: None,
+            reg_date_to: None,
+            lst_date_from: None,
+            lst_date_to: None,
+        },
+        lib_id,
+        lib_group: None,
+        lib_type: None,
+        results_get_position: None,
+        results_num: 100,
+    }
+}
+
+/// 検索結果の提供館の参加館プロファイルを取得し、各レコードに連絡先を付与する。
+///
+/// キャッシュにない提供館は、図書館名をまとめて `lib-name` で検索し、図書館コードで照合する。
+/// まとめた検索で見つからなかった提供館は、図書館コードを指定して個別に検索する。
+pub async fn attach_libraries(
+    service: &CrdService,
+    results: &mut [CrdSearchResult],
+) -> Result<(), ErrorData> {
+    let mut missing: Vec<(String, String)> = Vec::new();
+    {
+        let cache = service.library_cache.0.lock().await;
+        for result in results.iter() {
+            if matches!(result, CrdSearchResult::Profile { .. })
+                || cache.contains_key(result.lib_id())
+                || missing.iter().any(|(id, _)| id == result.lib_id())
+            {
+                continue;
+            }
+            missing.push((result.lib_id().to_string(), result.lib_name().to_string()));
+        }
+    }
+
+    let mut found: HashMap<String, Option<LibraryEntry>> = HashMap::new();
+    for chunk in missing.chunks(LIBRARY_BATCH_SIZE) {
+        let names = chunk
+            .iter()
+            .map(|(_, name)| name.replace(['"', ' ', '　'], ""))
+            .collect::<Vec<_>>();
+        let request = profile_request(
+            Some(format!("lib-name any {}", quote(&names.join(" ")))),
+            None,
+        );
+        let response = service.search_all(request, chunk.len() * 5).await?;
+        for entry in response.results.iter().filter_map(LibraryEntry::new) {
+            if chunk.iter().any(|(id, _)| *id == entry.lib_id) {
+                found.insert(entry.lib_id.clone(), Some(entry));
+            }
+        }
+    }
+    for (lib_id, lib_name) in &missing {
+        if found.contains_key(lib_id) {
+            continue;
+        }
+        let request = profile_request(
+            Some(format!("lib-name any {}", quote(lib_name))),
+            Some(lib_id.clone()),
+        );
+        let response = service.search_all(request, 1).await?;
+        let entry = response.results.iter().find_map(LibraryEntry::new);
+        found.insert(lib_id.clone(), entry);
+    }
+
+    let mut cache = service.library_cache.0.lock().await;
+    cache.extend(found);
+    for result in results.iter_mut() {
+        if let Some(entry) = cache.get(result.lib_id()) {
+            result.set_library(entry.clone());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_isil, normalize_prefecture};
+
+    #[test]
+    fn test_normalize_prefecture() {
+        assert_eq!(normalize_prefecture("東京"), Some("東京都"));
+        assert_eq!(normalize_prefecture("京都府"), Some("京都府"));
+        assert_eq!(normalize_prefecture("京都"), Some("京都府"));
+        assert_eq!(normalize_prefecture("北海道"), Some("北海道"));
+        assert_eq!(normalize_prefecture("神奈川"), Some("神奈川県"));
+        assert_eq!(normalize_prefecture("北海"), None);
+        assert_eq!(normalize_prefecture("東京府"), None);
+    }
+
+    #[test]
+    fn test_normalize_isil() {
+        assert_eq!(normalize_isil("JP-1000001").unwrap(), "JP-1000001");
+        assert_eq!(
+            normalize_isil(" ｊｐ－１０００００１ ").unwrap(),
+            "JP-1000001"
+        );
+        assert_eq!(normalize_isil("DE-Tue120").unwrap(), "DE-Tue120");
+        assert!(normalize_isil("JP1000001").is_err());
+        assert!(normalize_isil("JP-100001").is_err());
+        assert!(normalize_isil("US-ABCDEFGHIJKLMN").is_err());
+    }
+}
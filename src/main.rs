@@ -1,14 +1,60 @@
+use clap::{Args, Parser, Subcommand};
 use rmcp::ServiceExt;
+use std::path::PathBuf;
 use tokio::io::{stdin, stdout};
 
+mod completion;
 mod crd;
+mod cursor;
+mod date;
+mod export;
+mod isbn;
+mod library;
+mod mirror;
+mod ndc;
+mod query;
 mod req;
 mod res;
 mod service;
+mod vocabulary;
 
+use crate::export::ExportArgs;
+use crate::mirror::SyncArgs;
 use crate::service::CrdService;
 use tracing_subscriber::EnvFilter;
 
+#[derive(Parser, Debug)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+#[derive(Args, Debug)]
+struct ServeArgs {
+    /// ローカルミラーのSQLiteデータベース。指定した場合はローカルミラーを使うツールが有効になる。
+    #[arg(long, env = "CRD_MIRROR_PATH")]
+    db: Option<PathBuf>,
+    /// 検索式の展開(expand)に使う同義語辞書。1行に同じ意味の語をタブまたはカンマ区切りで並べる。
+    #[arg(long, env = "CRD_SYNONYMS_PATH")]
+    synonyms: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// MCPサーバーとして起動する(デフォルト)
+    Serve(ServeArgs),
+    /// CRDのレコードをローカルのSQLiteデータベースへ同期する
+    Sync(SyncArgs),
+    /// 検索結果をCSV・JSONL・TSVで出力する
+    Export(ExportArgs),
+    /// ローカルミラーのレファレンス事例の埋め込みベクトルを計算する
+    #[cfg(feature = "embeddings")]
+    Embed(mirror::EmbedArgs),
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
@@ -17,13 +63,29 @@ async fn main() -> anyhow::Result<()> {
         .with_ansi(false)
         .init();
 
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Serve(cli.serve)) {
+        Command::Serve(args) => serve(args).await,
+        Command::Sync(args) => mirror::sync(&CrdService::new(), args).await,
+        Command::Export(args) => export::run(&CrdService::new(), args).await,
+        #[cfg(feature = "embeddings")]
+        Command::Embed(args) => mirror::embed(args).await,
+    }
+}
+
+async fn serve(args: ServeArgs) -> anyhow::Result<()> {
     tracing::info!("Starting CRD MCP server");
 
     let transport = (stdin(), stdout());
 
-    let service = CrdService::new().serve(transport).await.inspect_err(|e| {
-        tracing::error!("serving error: {:?}", e);
-    })?;
+    let service = CrdService::new()
+        .with_mirror(args.db)
+        .with_synonyms(args.synonyms)?
+        .serve(transport)
+        .await
+        .inspect_err(|e| {
+            tracing::error!("serving error: {:?}", e);
+        })?;
 
     service.waiting().await?;
     Ok(())
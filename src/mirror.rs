@@ -0,0 +1,945 @@
+#[cfg(feature = "embeddings")]
+mod embedding;
+mod search;
+
+use crate::crd::{
+    CrdCollectionResult, CrdManualResult, CrdProfileResult, CrdReferenceResult, CrdResult,
+    CrdResultSet, MalformedResult, parse_result, split_results,
+};
+use crate::cursor::fnv1a;
+use crate::date::today;
+use crate::library::{LIST_SINCE, quote};
+use crate::mirror::search::SearchDocument;
+#[cfg(feature = "embeddings")]
+use crate::req::SimilarCasesRequest;
+use crate::req::{Condition, CrdSearchRequest, LocalSearchRequest, ReqType};
+use crate::res::LocalSearchResponse;
+#[cfg(feature = "embeddings")]
+use crate::res::{SimilarCase, SimilarCasesResponse};
+use crate::service::CrdService;
+use chrono::{Days, NaiveDate, Utc};
+#[cfg(feature = "embeddings")]
+pub use embedding::Embedder;
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// ローカルミラーの保存先のデフォルト
+pub const DEFAULT_MIRROR_PATH: &str = "crd.sqlite3";
+/// 初回同期の開始日(CRDの公開開始以前)
+const INITIAL_SYNC_DATE: NaiveDate = NaiveDate::from_ymd_opt(2002, 1, 1).unwrap();
+/// データベースのスキーマのバージョン(`PRAGMA user_version`)
+const SCHEMA_VERSION: i32 = 1;
+/// 同期対象の検索対象
+const SYNC_TYPES: [ReqType; 4] = [
+    ReqType::Reference,
+    ReqType::Manual,
+    ReqType::Collection,
+    ReqType::Profile,
+];
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS sync_state (
+    type TEXT PRIMARY KEY,
+    high_water TEXT NOT NULL,
+    synced_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS reference (
+    sys_id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    question TEXT NOT NULL,
+    reg_id TEXT NOT NULL,
+    answer TEXT NOT NULL,
+    crt_date TEXT NOT NULL,
+    solution INTEGER,
+    keywords TEXT,
+    classes TEXT,
+    res_type TEXT,
+    con_type TEXT,
+    bibls TEXT,
+    ans_proc TEXT,
+    referrals TEXT,
+    pre_res TEXT,
+    note TEXT,
+    ptn_type TEXT,
+    contri TEXT,
+    reg_date TEXT NOT NULL,
+    lst_date TEXT NOT NULL,
+    lib_id TEXT NOT NULL,
+    lib_name TEXT NOT NULL,
+    file_num INTEGER NOT NULL,
+    raw_xml TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS manual (
+    sys_id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    theme TEXT NOT NULL,
+    reg_id TEXT NOT NULL,
+    guide TEXT NOT NULL,
+    crt_date TEXT NOT NULL,
+    completion INTEGER,
+    keywords TEXT,
+    classes TEXT,
+    bibls TEXT,
+    note TEXT,
+    reg_date TEXT NOT NULL,
+    lst_date TEXT NOT NULL,
+    lib_id TEXT NOT NULL,
+    lib_name TEXT NOT NULL,
+    file_num INTEGER NOT NULL,
+    raw_xml TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS collection (
+    sys_id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    col_name TEXT NOT NULL,
+    pro_key TEXT NOT NULL,
+    reg_id TEXT NOT NULL,
+    outline TEXT NOT NULL,
+    origin TEXT,
+    restriction TEXT,
+    catalog TEXT,
+    literature TEXT,
+    number TEXT,
+    "continue" TEXT,
+    keywords TEXT,
+    classes TEXT,
+    note TEXT,
+    reg_date TEXT NOT NULL,
+    lst_date TEXT NOT NULL,
+    lib_id TEXT NOT NULL,
+    lib_name TEXT NOT NULL,
+    file_num INTEGER NOT NULL,
+    raw_xml TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS profile (
+    lib_id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    lib_type TEXT NOT NULL,
+    name TEXT NOT NULL,
+    abbr TEXT NOT NULL,
+    pro_key TEXT NOT NULL,
+    zip_code TEXT NOT NULL,
+    add_pref TEXT NOT NULL,
+    add_city TEXT NOT NULL,
+    add_street TEXT NOT NULL,
+    tel1 TEXT NOT NULL,
+    tel1_note TEXT,
+    tel2 TEXT,
+    tel2_note TEXT,
+    tel3 TEXT,
+    tel3_note TEXT,
+    fax TEXT,
+    e_mail TEXT,
+    lib_url TEXT,
+    open_info TEXT,
+    restriction TEXT,
+    outline TEXT,
+    feature TEXT,
+    notes TEXT,
+    access TEXT,
+    isil TEXT,
+    reg_date TEXT NOT NULL,
+    lst_date TEXT NOT NULL,
+    lib_name TEXT NOT NULL,
+    file_num INTEGER NOT NULL,
+    raw_xml TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sync_errors (
+    id INTEGER PRIMARY KEY,
+    type TEXT,
+    record_id TEXT UNIQUE,
+    xml_hash TEXT UNIQUE,
+    error TEXT NOT NULL,
+    raw_xml TEXT NOT NULL,
+    failed_at TEXT NOT NULL
+);
+"#;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SyncArgs {
+    /// 同期先のSQLiteデータベース
+    #[arg(long, env = "CRD_MIRROR_PATH", default_value = DEFAULT_MIRROR_PATH)]
+    pub db: PathBuf,
+    /// 同期する検索対象(reference, manual, collection, profile)。
+    /// 指定がない場合は全ての種類を同期する。
+    #[arg(long = "type")]
+    pub types: Vec<ReqType>,
+    /// 同期の開始日(YYYYMMDD)。指定がない場合は前回同期した日から再開する。
+    #[arg(long, value_parser = parse_date)]
+    pub since: Option<NaiveDate>,
+    /// 1回の検索で対象とする最終更新日の期間(日数)
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    pub window_days: u64,
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y%m%d").map_err(|e| format!("YYYYMMDDで指定してください: {}", e))
+}
+
+fn to_json<T: Serialize>(value: &Option<T>) -> anyhow::Result<Option<String>> {
+    Ok(value.as_ref().map(serde_json::to_string).transpose()?)
+}
+
+/// 1ページ分のレコードの保存結果
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StoredPage {
+    /// 保存したレコード数
+    pub stored: usize,
+    /// 読み込めず `sync_errors` に記録したレコード数
+    pub failed: usize,
+    /// 読み込めなかったレコードのうち、登録番号も読み取れず取得し直せないレコード数
+    pub unidentified: usize,
+}
+
+/// レコードを取得し直すための識別子。登録番号、参加館プロファイルは図書館コードとする。
+fn record_id(result: &CrdResult) -> &str {
+    match result {
+        CrdResult::Reference(r) => &r.system.sys_id,
+        CrdResult::Manual(r) => &r.system.sys_id,
+        CrdResult::Collection(r) => &r.system.sys_id,
+        CrdResult::Profile(r) => &r.system.lib_id,
+    }
+}
+
+/// CRDのレコードを保存するローカルのSQLiteデータベース
+pub struct Mirror {
+    conn: Connection,
+}
+
+impl Mirror {
+    pub fn open(path: &Path) -> anyhow::Result<Mirror> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch(search::INDEX_SCHEMA)?;
+        #[cfg(feature = "embeddings")]
+        conn.execute_batch(embedding::EMBEDDING_SCHEMA)?;
+        let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version < SCHEMA_VERSION {
+            search::rebuild_index(&conn)?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        Ok(Mirror { conn })
+    }
+
+    /// 保存済みのレコードを全文検索する。
+    pub fn search(&self, request: &LocalSearchRequest) -> anyhow::Result<LocalSearchResponse> {
+        search::search(&self.conn, request)
+    }
+
+    /// キーワードのあるレコードの登録番号とキーワード
+    pub fn keyword_sets(&self) -> anyhow::Result<Vec<(String, Vec<String>)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT sys_id, keywords FROM reference WHERE keywords IS NOT NULL \
+             UNION ALL SELECT sys_id, keywords FROM manual WHERE keywords IS NOT NULL \
+             UNION ALL SELECT sys_id, keywords FROM collection WHERE keywords IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut sets = Vec::new();
+        for row in rows {
+            let (sys_id, keywords) = row?;
+            sets.push((sys_id, serde_json::from_str(&keywords)?));
+        }
+        Ok(sets)
+    }
+
+    /// 参加館プロファイルの図書館コード・図書館名（正式）・図書館名（略式）
+    pub fn libraries(&self) -> anyhow::Result<Vec<(String, String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT lib_id, name, abbr FROM profile ORDER BY lib_id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// 前回の同期で取得済みの最終更新日
+    pub fn high_water(&self, ty: ReqType) -> anyhow::Result<Option<NaiveDate>> {
+        let date: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT high_water FROM sync_state WHERE type = ?1",
+                params![ty.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        date.map(|d| parse_date(&d))
+            .transpose()
+            .map_err(anyhow::Error::msg)
+    }
+
+    fn set_high_water(&self, ty: ReqType, date: NaiveDate) -> anyhow::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO sync_state (type, high_water, synced_at) VALUES (?1, ?2, ?3)",
+            params![
+                ty.to_string(),
+                date.format("%Y%m%d").to_string(),
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// レスポンスのXMLに含まれるレコードを保存する。
+    ///
+    /// 読み込めなかったレコードは `sync_errors` に記録し、次回の同期で登録番号を指定して取得し直す。
+    /// 保存できたレコードは `sync_errors` から除く。
+    pub fn store_page(&mut self, raw_xml: &str) -> anyhow::Result<StoredPage> {
+        let tx = self.conn.transaction()?;
+        let mut page = StoredPage::default();
+        for fragment in split_results(raw_xml)? {
+            match parse_result(fragment) {
+                Ok(result) => {
+                    let id = record_id(&result).to_string();
+                    store_result(&tx, result, fragment)?;
+                    tx.execute("DELETE FROM sync_errors WHERE record_id = ?1", params![id])?;
+                    page.stored += 1;
+                }
+                Err(e) => {
+                    let malformed = MalformedResult::new(fragment, e.to_string());
+                    let id = match malformed.ty.as_deref() {
+                        Some("profile") => malformed.lib_id.clone(),
+                        _ => malformed.sys_id.clone(),
+                    }
+                    .filter(|id| !id.is_empty());
+                    tracing::warn!(
+                        "failed to read record {}: {}",
+                        id.as_deref().unwrap_or("(unknown id)"),
+                        malformed.error
+                    );
+                    // 登録番号が読み取れないレコードは、XMLのハッシュ値で同じレコードとみなす
+                    let xml_hash = id
+                        .is_none()
+                        .then(|| format!("{:016x}", fnv1a(&malformed.raw_xml)));
+                    if xml_hash.is_some() {
+                        page.unidentified += 1;
+                    }
+                    tx.execute(
+                        "INSERT OR REPLACE INTO sync_errors \
+                         (type, record_id, xml_hash, error, raw_xml, failed_at) \
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        params![
+                            malformed.ty,
+                            id,
+                            xml_hash,
+                            malformed.error,
+                            malformed.raw_xml,
+                            Utc::now().to_rfc3339()
+                        ],
+                    )?;
+                    page.failed += 1;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(page)
+    }
+
+    /// 読み込めなかったレコードのうち、登録番号(参加館プロファイルは図書館コード)が分かり取得し直せるもの
+    pub fn sync_error_ids(&self, ty: ReqType) -> anyhow::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT record_id FROM sync_errors WHERE type = ?1 AND record_id IS NOT NULL ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![ty.to_string()], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// CRDから削除され、取得し直せないレコードを `sync_errors` から除く。
+    fn clear_sync_error(&self, id: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute("DELETE FROM sync_errors WHERE record_id = ?1", params![id])?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embeddings")]
+impl Mirror {
+    /// 埋め込みが未計算のレファレンス事例の埋め込みを計算し、計算した件数を返す。
+    pub fn update_embeddings(&self, embedder: &mut Embedder) -> anyhow::Result<usize> {
+        embedding::update_embeddings(&self.conn, embedder)
+    }
+
+    /// 文章または登録番号で指定したレファレンス事例に類似する事例を返す。
+    ///
+    /// 文章を指定した場合は、必要になった時点で `embedder` を初期化する。
+    pub fn similar_cases(
+        &self,
+        request: &SimilarCasesRequest,
+        embedder: &mut Option<Embedder>,
+    ) -> anyhow::Result<SimilarCasesResponse> {
+        let query = match (&request.text, &request.sys_id) {
+            (Some(text), None) => {
+                if embedder.is_none() {
+                    *embedder = Some(Embedder::new()?);
+                }
+                embedder.as_mut().unwrap().embed_query(text)?
+            }
+            (None, Some(sys_id)) => embedding::stored_vector(&self.conn, sys_id)?.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "登録番号 {} の埋め込みがありません。sync と embed サブコマンドを実行してください",
+                    sys_id
+                )
+            })?,
+            _ => anyhow::bail!("text と sys_id のどちらか一方を指定してください"),
+        };
+
+        let nearest = embedding::nearest(
+            &self.conn,
+            &query,
+            request.sys_id.as_deref(),
+            request.limit as usize,
+        )?;
+        let mut results = Vec::with_capacity(nearest.len());
+        for (sys_id, similarity) in nearest {
+            let raw_xml: String = self.conn.query_row(
+                "SELECT raw_xml FROM reference WHERE sys_id = ?1",
+                params![sys_id],
+                |row| row.get(0),
+            )?;
+            results.push(SimilarCase {
+                similarity,
+                record: parse_result(&raw_xml)?.into(),
+            });
+        }
+        Ok(SimilarCasesResponse { results })
+    }
+}
+
+#[cfg(feature = "embeddings")]
+#[derive(clap::Args, Debug, Clone)]
+pub struct EmbedArgs {
+    /// ローカルミラーのSQLiteデータベース
+    #[arg(long, env = "CRD_MIRROR_PATH", default_value = DEFAULT_MIRROR_PATH)]
+    pub db: PathBuf,
+}
+
+/// ローカルミラーのレファレンス事例のうち、埋め込みが未計算のものを計算する。
+#[cfg(feature = "embeddings")]
+pub async fn embed(args: EmbedArgs) -> anyhow::Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let mirror = Mirror::open(&args.db)?;
+        let mut embedder = Embedder::new()?;
+        let count = mirror.update_embeddings(&mut embedder)?;
+        tracing::info!("embedded {} reference records", count);
+        Ok(())
+    })
+    .await?
+}
+
+fn store_result(tx: &Transaction, result: CrdResult, raw_xml: &str) -> anyhow::Result<()> {
+    match result {
+        CrdResult::Reference(CrdReferenceResult {
+            url,
+            question,
+            reg_id,
+            answer,
+            crt_date,
+            solution,
+            keywords,
+            classes,
+            res_type,
+            con_type,
+            bibls,
+            ans_proc,
+            referrals,
+            pre_res,
+            note,
+            ptn_type,
+            contri,
+            system,
+        }) => {
+            tx.execute(
+                "INSERT OR REPLACE INTO reference (sys_id, url, question, reg_id, answer, crt_date, \
+                 solution, keywords, classes, res_type, con_type, bibls, ans_proc, referrals, \
+                 pre_res, note, ptn_type, contri, reg_date, lst_date, lib_id, lib_name, file_num, \
+                 raw_xml) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, \
+                 ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24)",
+                params![
+                    system.sys_id,
+                    url,
+                    question,
+                    reg_id,
+                    answer,
+                    crt_date.raw,
+                    solution.code(),
+                    to_json(&keywords)?,
+                    to_json(&classes)?,
+                    res_type,
+                    con_type,
+                    to_json(&bibls)?,
+                    ans_proc,
+                    to_json(&referrals)?,
+                    pre_res,
+                    note,
+                    ptn_type,
+                    to_json(&contri)?,
+                    system.reg_date.raw,
+                    system.lst_date.raw,
+                    system.lib_id,
+                    system.lib_name,
+                    system.file_num,
+                    raw_xml,
+                ],
+            )?;
+            search::index(
+                tx,
+                SearchDocument {
+                    ty: ReqType::Reference,
+                    id: &system.sys_id,
+                    title: &question,
+                    answer: Some(&answer),
+                    guide: None,
+                    outline: None,
+                    keywords: keywords.as_deref(),
+                },
+            )?;
+        }
+        CrdResult::Manual(CrdManualResult {
+            url,
+            theme,
+            reg_id,
+            guide,
+            crt_date,
+            completion,
+            keywords,
+            classes,
+            bibls,
+            note,
+            system,
+        }) => {
+            tx.execute(
+                "INSERT OR REPLACE INTO manual (sys_id, url, theme, reg_id, guide, crt_date, \
+                 completion, keywords, classes, bibls, note, reg_date, lst_date, lib_id, lib_name, \
+                 file_num, raw_xml) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, \
+                 ?13, ?14, ?15, ?16, ?17)",
+                params![
+                    system.sys_id,
+                    url,
+                    theme,
+                    reg_id,
+                    guide,
+                    crt_date.raw,
+                    completion.code(),
+                    to_json(&keywords)?,
+                    to_json(&classes)?,
+                    to_json(&bibls)?,
+                    note,
+                    system.reg_date.raw,
+                    system.lst_date.raw,
+                    system.lib_id,
+                    system.lib_name,
+                    system.file_num,
+                    raw_xml,
+                ],
+            )?;
+            search::index(
+                tx,
+                SearchDocument {
+                    ty: ReqType::Manual,
+                    id: &system.sys_id,
+                    title: &theme,
+                    answer: None,
+                    guide: Some(&guide),
+                    outline: None,
+                    keywords: keywords.as_deref(),
+                },
+            )?;
+        }
+        CrdResult::Collection(CrdCollectionResult {
+            url,
+            col_name,
+            pro_key,
+            reg_id,
+            outline,
+            origin,
+            restriction,
+            catalog,
+            literature,
+            number,
+            r#continue,
+            keywords,
+            classes,
+            note,
+            system,
+        }) => {
+            tx.execute(
+                "INSERT OR REPLACE INTO collection (sys_id, url, col_name, pro_key, reg_id, \
+                 outline, origin, restriction, catalog, literature, number, \"continue\", keywords, \
+                 classes, note, reg_date, lst_date, lib_id, lib_name, file_num, raw_xml) VALUES \
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, \
+                 ?18, ?19, ?20, ?21)",
+                params![
+                    system.sys_id,
+                    url,
+                    col_name,
+                    pro_key,
+                    reg_id,
+                    outline,
+                    origin,
+                    restriction,
+                    catalog,
+                    literature,
+                    number,
+                    r#continue.code(),
+                    to_json(&keywords)?,
+                    to_json(&classes)?,
+                    note,
+                    system.reg_date.raw,
+                    system.lst_date.raw,
+                    system.lib_id,
+                    system.lib_name,
+                    system.file_num,
+                    raw_xml,
+                ],
+            )?;
+            search::index(
+                tx,
+                SearchDocument {
+                    ty: ReqType::Collection,
+                    id: &system.sys_id,
+                    title: &col_name,
+                    answer: None,
+                    guide: None,
+                    outline: Some(&outline),
+                    keywords: keywords.as_deref(),
+                },
+            )?;
+        }
+        CrdResult::Profile(CrdProfileResult {
+            url,
+            ty,
+            name,
+            abbr,
+            pro_key,
+            zip_code,
+            add_pref,
+            add_city,
+            add_street,
+            tel1,
+            tel1_note,
+            tel2,
+            tel2_note,
+            tel3,
+            tel3_note,
+            fax,
+            e_mail,
+            lib_url,
+            open_info,
+            restriction,
+            outline,
+            feature,
+            notes,
+            access,
+            isil,
+            system,
+        }) => {
+            tx.execute(
+                "INSERT OR REPLACE INTO profile (lib_id, url, lib_type, name, abbr, pro_key, \
+                 zip_code, add_pref, add_city, add_street, tel1, tel1_note, tel2, tel2_note, tel3, \
+                 tel3_note, fax, e_mail, lib_url, open_info, restriction, outline, feature, notes, \
+                 access, isil, reg_date, lst_date, lib_name, file_num, raw_xml) VALUES (?1, ?2, \
+                 ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, \
+                 ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31)",
+                params![
+                    system.lib_id,
+                    url,
+                    ty.code(),
+                    name,
+                    abbr,
+                    pro_key,
+                    zip_code,
+                    add_pref,
+                    add_city,
+                    add_street,
+                    tel1,
+                    tel1_note,
+                    tel2,
+                    tel2_note,
+                    tel3,
+                    tel3_note,
+                    fax,
+                    e_mail,
+                    lib_url,
+                    open_info,
+                    restriction,
+                    outline,
+                    feature,
+                    notes,
+                    access,
+                    isil,
+                    system.reg_date.raw,
+                    system.lst_date.raw,
+                    system.lib_name,
+                    system.file_num,
+                    raw_xml,
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// CRDのレコードを最終更新日の期間ごとに取得し、ローカルのデータベースへ保存する。
+///
+/// 期間ごとに取得が完了した時点で最終更新日の到達点を記録するため、
+/// 次回以降の同期では前回の到達点以降に更新されたレコードのみを取得する。
+pub async fn sync(service: &CrdService, args: SyncArgs) -> anyhow::Result<()> {
+    let mut mirror = Mirror::open(&args.db)?;
+    let types: Vec<ReqType> = if args.types.is_empty() || args.types.contains(&ReqType::All) {
+        SYNC_TYPES.to_vec()
+    } else {
+        args.types.clone()
+    };
+    let today = today();
+    let mut fetch = async |request| service.crd_search_raw(request).await;
+
+    for ty in types {
+        let from = match args.since {
+            Some(since) => since,
+            None => mirror.high_water(ty)?.unwrap_or(INITIAL_SYNC_DATE),
+        };
+        let retried = retry_sync_errors(&mut fetch, &mut mirror, ty).await?;
+        tracing::info!("syncing {} from {}", ty, from);
+        let page = sync_windows(&mut fetch, &mut mirror, ty, from, today, args.window_days).await?;
+        tracing::info!("synced {} records of {}", retried.stored + page.stored, ty);
+        if page.unidentified > 0 {
+            tracing::warn!(
+                "{} records of {} could not be identified and are kept in sync_errors",
+                page.unidentified,
+                ty
+            );
+        }
+        let retriable = page.failed - page.unidentified + retried.failed;
+        if retriable > 0 {
+            tracing::warn!(
+                "{} records of {} could not be read and will be retried on the next sync",
+                retriable,
+                ty
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `from` から `to` までを `window_days` 日ごとの期間に分けて取得し、期間ごとに到達点を記録する。
+///
+/// 登録番号が読み取れないレコードは `sync_errors` に残し、到達点は進める。
+async fn sync_windows(
+    fetch: &mut impl AsyncFnMut(CrdSearchRequest) -> anyhow::Result<String>,
+    mirror: &mut Mirror,
+    ty: ReqType,
+    mut from: NaiveDate,
+    to: NaiveDate,
+    window_days: u64,
+) -> anyhow::Result<StoredPage> {
+    let mut total = StoredPage::default();
+    while from <= to {
+        let window_to = (from + Days::new(window_days - 1)).min(to);
+        let page = sync_window(fetch, mirror, ty, from, window_to).await?;
+        total.stored += page.stored;
+        total.failed += page.failed;
+        total.unidentified += page.unidentified;
+        mirror.set_high_water(ty, window_to)?;
+        from = window_to + Days::new(1);
+    }
+    Ok(total)
+}
+
+/// 前回までの同期で読み込めなかったレコードを、登録番号を指定して取得し直す。
+async fn retry_sync_errors(
+    fetch: &mut impl AsyncFnMut(CrdSearchRequest) -> anyhow::Result<String>,
+    mirror: &mut Mirror,
+    ty: ReqType,
+) -> anyhow::Result<StoredPage> {
+    let mut total = StoredPage::default();
+    for id in mirror.sync_error_ids(ty)? {
+        // 参加館プロファイルは図書館コードで、それ以外は登録番号で検索する
+        let profile = ty == ReqType::Profile;
+        let request = CrdSearchRequest {
+            ty,
+            condition: Condition {
+                query: (!profile).then(|| format!("sys-id = {}", quote(&id))),
+                crt_date_from: None,
+                crt_date_to: None,
+                reg_date_from: profile.then(|| LIST_SINCE.to_string()),
+                reg_date_to: None,
+                lst_date_from: None,
+                lst_date_to: None,
+            },
+            lib_id: profile.then(|| id.clone()),
+            lib_group: None,
+            lib_type: None,
+            results_get_position: None,
+            results_num: 1,
+        };
+        let raw_xml = fetch(request).await?;
+        let page: CrdResultSet = quick_xml::de::from_str(&raw_xml)?;
+        if page.results_cd == 0 && page.hit_num == Some(0) {
+            mirror.clear_sync_error(&id)?;
+            continue;
+        }
+        let stored = mirror.store_page(&raw_xml)?;
+        total.stored += stored.stored;
+        total.failed += stored.failed;
+    }
+    Ok(total)
+}
+
+async fn sync_window(
+    fetch: &mut impl AsyncFnMut(CrdSearchRequest) -> anyhow::Result<String>,
+    mirror: &mut Mirror,
+    ty: ReqType,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> anyhow::Result<StoredPage> {
+    let mut position = 0;
+    let mut stored = StoredPage::default();
+    loop {
+        let request = CrdSearchRequest {
+            ty,
+            condition: Condition {
+                query: None,
+                crt_date_from: None,
+                crt_date_to: None,
+                reg_date_from: None,
+                reg_date_to: None,
+                lst_date_from: Some(from.format("%Y%m%d").to_string()),
+                lst_date_to: Some(to.format("%Y%m%d").to_string()),
+            },
+            lib_id: None,
+            lib_group: None,
+            lib_type: None,
+            results_get_position: Some(position),
+            results_num: 100,
+        };
+        let raw_xml = fetch(request).await?;
+        let page: CrdResultSet = quick_xml::de::from_str(&raw_xml)?;
+        if page.results_cd != 0 {
+            let message = page
+                .err_list
+                .unwrap_or_default()
+                .into_iter()
+                .map(|e| e.err_item.err_msg)
+                .collect::<Vec<_>>()
+                .join(", ");
+            anyhow::bail!("CRD API error: {}", message);
+        }
+        let page_stored = mirror.store_page(&raw_xml)?;
+        stored.stored += page_stored.stored;
+        stored.failed += page_stored.failed;
+        stored.unidentified += page_stored.unidentified;
+        position = page.results_get_position + page.results_num;
+        if page.results_num == 0 || position >= page.hit_num.unwrap_or(0) {
+            break;
+        }
+    }
+    Ok(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mirror, sync_windows};
+    use crate::req::{LocalSearchRequest, MatchMode, ReqType};
+    use chrono::NaiveDate;
+    use std::path::Path;
+
+    const REFERENCE_XML: &str = include_str!("fixtures/reference.xml");
+
+    #[test]
+    fn test_store_page() {
+        let mut mirror = Mirror::open(Path::new(":memory:")).unwrap();
+        assert_eq!(mirror.store_page(REFERENCE_XML).unwrap().stored, 2);
+        // 同じレコードは上書きされる
+        assert_eq!(mirror.store_page(REFERENCE_XML).unwrap().stored, 2);
+        let count: i64 = mirror
+            .conn
+            .query_row("SELECT count(*) FROM reference", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+
+        assert_eq!(mirror.high_water(ReqType::Reference).unwrap(), None);
+        let date = NaiveDate::from_ymd_opt(2023, 4, 7).unwrap();
+        mirror.set_high_water(ReqType::Reference, date).unwrap();
+        assert_eq!(mirror.high_water(ReqType::Reference).unwrap(), Some(date));
+    }
+
+    #[test]
+    fn test_sync_errors() {
+        let mut mirror = Mirror::open(Path::new(":memory:")).unwrap();
+        // 読み込めないレコードと空の<result/>は、sync_errorsに記録する
+        let xml = REFERENCE_XML
+            .replacen("<file-num>1</file-num>", "<file-num>x</file-num>", 1)
+            .replacen("</result>", "</result><result/>", 1);
+        let page = mirror.store_page(&xml).unwrap();
+        assert_eq!(page.stored, 1);
+        assert_eq!(page.failed, 2);
+        assert_eq!(page.unidentified, 1);
+        assert_eq!(
+            mirror.sync_error_ids(ReqType::Reference).unwrap(),
+            ["1000300002"]
+        );
+
+        // 取得し直して保存できたレコードは除く
+        mirror.store_page(REFERENCE_XML).unwrap();
+        assert!(
+            mirror
+                .sync_error_ids(ReqType::Reference)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_windows_unidentified() {
+        let mut mirror = Mirror::open(Path::new(":memory:")).unwrap();
+        // 登録番号が読み取れないレコードがあっても到達点は進み、sync_errorsには1行だけ残る
+        let xml = REFERENCE_XML
+            .replace("<hit_num>2</hit_num>", "<hit_num>3</hit_num>")
+            .replace(
+                "<results_num>2</results_num>",
+                "<results_num>3</results_num>",
+            )
+            .replacen("</result>", "</result><result/>", 1);
+        let mut fetch = async |_| Ok(xml.clone());
+        let date = |day| NaiveDate::from_ymd_opt(2023, 4, day).unwrap();
+        for (from, to) in [(date(1), date(7)), (date(7), date(13))] {
+            let page = sync_windows(&mut fetch, &mut mirror, ReqType::Reference, from, to, 5)
+                .await
+                .unwrap();
+            assert_eq!(page.unidentified, 2);
+            assert_eq!(mirror.high_water(ReqType::Reference).unwrap(), Some(to));
+            let count: i64 = mirror
+                .conn
+                .query_row("SELECT count(*) FROM sync_errors", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(count, 1);
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let mut mirror = Mirror::open(Path::new(":memory:")).unwrap();
+        mirror.store_page(REFERENCE_XML).unwrap();
+        let request = |query: &str| LocalSearchRequest {
+            query: query.to_string(),
+            ty: None,
+            mode: MatchMode::All,
+            limit: 10,
+        };
+
+        let res = mirror.search(&request("北海道 地名")).unwrap();
+        assert_eq!(res.hit_count, 1);
+        assert_eq!(res.results[0].record.sys_id(), Some("1000300002"));
+
+        let res = mirror.search(&request("開拓使")).unwrap();
+        assert_eq!(res.hit_count, 1);
+        assert_eq!(res.results[0].record.sys_id(), Some("1000300001"));
+
+        // 2件とも質問に含むが、キーワードにも含む事例が上位となる
+        let res = mirror.search(&request("北海道")).unwrap();
+        assert_eq!(res.hit_count, 2);
+        assert_eq!(res.results[0].record.sys_id(), Some("1000300001"));
+        assert!(res.results[0].score > res.results[1].score);
+    }
+}
@@ -0,0 +1,182 @@
+use fastembed::{EmbeddingModel, TextEmbedding, TextInitOptions};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::fmt::{Debug, Formatter};
+
+/// 埋め込みに使用するモデル
+const MODEL: EmbeddingModel = EmbeddingModel::MultilingualE5Small;
+/// 保存した埋め込みベクトルを計算したモデルの名前
+const MODEL_NAME: &str = "multilingual-e5-small";
+/// 一度に埋め込みを計算する件数
+const BATCH_SIZE: usize = 64;
+
+/// レファレンス事例の埋め込みベクトル
+///
+/// ベクトルは長さ1に正規化し、f32のリトルエンディアンのバイト列として保存する。
+pub(super) const EMBEDDING_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS embedding (
+    sys_id TEXT PRIMARY KEY,
+    model TEXT NOT NULL,
+    lst_date TEXT NOT NULL,
+    vector BLOB NOT NULL
+);
+"#;
+
+/// CPUで埋め込みベクトルを計算する。
+///
+/// 初回の使用時にモデルをダウンロードする。
+pub struct Embedder {
+    model: TextEmbedding,
+}
+
+impl Embedder {
+    pub fn new() -> anyhow::Result<Embedder> {
+        let model =
+            TextEmbedding::try_new(TextInitOptions::new(MODEL).with_show_download_progress(false))?;
+        Ok(Embedder { model })
+    }
+
+    /// 検索対象の文章の埋め込みを計算する。
+    fn embed_passages(&mut self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let texts = texts
+            .iter()
+            .map(|t| format!("passage: {}", t))
+            .collect::<Vec<_>>();
+        let vectors = self.model.embed(texts, Some(BATCH_SIZE))?;
+        Ok(vectors.into_iter().map(normalize).collect())
+    }
+
+    /// 検索語の埋め込みを計算する。
+    pub fn embed_query(&mut self, text: &str) -> anyhow::Result<Vec<f32>> {
+        let mut vectors = self.model.embed([format!("query: {}", text)], None)?;
+        Ok(normalize(vectors.remove(0)))
+    }
+}
+
+impl Debug for Embedder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Embedder")
+            .field("model", &MODEL_NAME)
+            .finish()
+    }
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+fn to_bytes(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// 埋め込みが未計算、または計算後に更新されたレファレンス事例の埋め込みを計算し、計算した件数を返す。
+///
+/// ミラーから削除されたレファレンス事例の埋め込みは削除する。
+pub(super) fn update_embeddings(
+    conn: &Connection,
+    embedder: &mut Embedder,
+) -> anyhow::Result<usize> {
+    let removed = conn.execute(
+        "DELETE FROM embedding \
+         WHERE NOT EXISTS (SELECT 1 FROM reference r WHERE r.sys_id = embedding.sys_id)",
+        [],
+    )?;
+    if removed > 0 {
+        tracing::info!("removed {} embeddings of deleted records", removed);
+    }
+    let mut stmt = conn.prepare(
+        "SELECT r.sys_id, r.question, r.answer, r.lst_date FROM reference r \
+         LEFT JOIN embedding e ON e.sys_id = r.sys_id \
+         WHERE e.sys_id IS NULL OR e.lst_date != r.lst_date OR e.model != ?1",
+    )?;
+    let rows = stmt
+        .query_map(params![MODEL_NAME], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                format!("{}\n{}", row.get::<_, String>(1)?, row.get::<_, String>(2)?),
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for chunk in rows.chunks(BATCH_SIZE) {
+        let texts = chunk.iter().map(|(_, t, _)| t.clone()).collect::<Vec<_>>();
+        let vectors = embedder.embed_passages(&texts)?;
+        for ((sys_id, _, lst_date), vector) in chunk.iter().zip(vectors) {
+            conn.execute(
+                "INSERT OR REPLACE INTO embedding (sys_id, model, lst_date, vector) \
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![sys_id, MODEL_NAME, lst_date, to_bytes(&vector)],
+            )?;
+        }
+        tracing::info!("embedded {} records", chunk.len());
+    }
+    Ok(rows.len())
+}
+
+/// 保存済みのレファレンス事例の埋め込みベクトル
+pub(super) fn stored_vector(conn: &Connection, sys_id: &str) -> anyhow::Result<Option<Vec<f32>>> {
+    let bytes: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT vector FROM embedding WHERE sys_id = ?1 AND model = ?2",
+            params![sys_id, MODEL_NAME],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(bytes.map(|b| from_bytes(&b)))
+}
+
+/// 類似度(コサイン類似度)の高い順に、`limit` 件の登録番号と類似度を返す。
+///
+/// ミラーに存在するレファレンス事例の埋め込みのみを対象とする。
+pub(super) fn nearest(
+    conn: &Connection,
+    query: &[f32],
+    exclude: Option<&str>,
+    limit: usize,
+) -> anyhow::Result<Vec<(String, f32)>> {
+    let mut stmt = conn.prepare(
+        "SELECT e.sys_id, e.vector FROM embedding e \
+         JOIN reference r ON r.sys_id = e.sys_id WHERE e.model = ?1",
+    )?;
+    let rows = stmt.query_map(params![MODEL_NAME], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+    })?;
+    let mut scored = Vec::new();
+    for row in rows {
+        let (sys_id, bytes) = row?;
+        if Some(sys_id.as_str()) != exclude {
+            let score = dot(query, &from_bytes(&bytes));
+            scored.push((sys_id, score));
+        }
+    }
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.truncate(limit);
+    Ok(scored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_bytes, normalize, to_bytes};
+
+    #[test]
+    fn test_vector_roundtrip() {
+        let vector = normalize(vec![3.0, 4.0]);
+        assert_eq!(vector, vec![0.6, 0.8]);
+        assert_eq!(from_bytes(&to_bytes(&vector)), vector);
+    }
+}
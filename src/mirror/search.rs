@@ -0,0 +1,301 @@
+use crate::crd::parse_result;
+use crate::req::{LocalSearchRequest, MatchMode, ReqType};
+use crate::res::{LocalSearchHit, LocalSearchResponse};
+use rusqlite::{Connection, OptionalExtension, params};
+use unicode_normalization::UnicodeNormalization;
+
+/// 全文検索用のテーブル
+///
+/// 日本語の文章は分かち書きされていないため、文字のバイグラムに分割した文字列を
+/// `unicode61` トークナイザで索引付けする。
+pub(super) const INDEX_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS search_doc (
+    rowid INTEGER PRIMARY KEY,
+    type TEXT NOT NULL,
+    id TEXT NOT NULL,
+    UNIQUE (type, id)
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS search_index USING fts5(
+    title, answer, guide, outline, keywords,
+    tokenize = 'unicode61'
+);
+"#;
+
+/// BM25の列ごとの重み(title, answer, guide, outline, keywords)
+const BM25_WEIGHTS: &str = "3.0, 1.0, 1.0, 1.0, 2.0";
+
+/// 全文検索の対象とする項目
+pub(super) struct SearchDocument<'a> {
+    pub ty: ReqType,
+    pub id: &'a str,
+    /// 質問・調査テーマ・コレクション名
+    pub title: &'a str,
+    pub answer: Option<&'a str>,
+    pub guide: Option<&'a str>,
+    pub outline: Option<&'a str>,
+    pub keywords: Option<&'a [String]>,
+}
+
+/// ひらがな・カタカナ・漢字
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3005}'..='\u{3007}'
+        | '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+    )
+}
+
+/// 文字列を正規化し、日本語の連続部分と英数字の語に分割する。
+fn runs(text: &str) -> Vec<(bool, Vec<char>)> {
+    let mut runs: Vec<(bool, Vec<char>)> = Vec::new();
+    for c in text.nfkc().flat_map(char::to_lowercase) {
+        if !c.is_alphanumeric() {
+            runs.push((false, Vec::new()));
+            continue;
+        }
+        let cjk = is_cjk(c);
+        match runs.last_mut() {
+            Some((kind, chars)) if *kind == cjk || chars.is_empty() => {
+                *kind = cjk;
+                chars.push(c);
+            }
+            _ => runs.push((cjk, vec![c])),
+        }
+    }
+    runs.retain(|(_, chars)| !chars.is_empty());
+    runs
+}
+
+/// 索引に登録するトークン列
+///
+/// 日本語の連続部分はバイグラムに分割し、末尾の1文字も単独のトークンとする。
+/// これにより、1文字の検索語を前方一致で、連続部分の境界をまたぐ検索語を
+/// フレーズで検索できる。
+pub fn tokenize(text: &str) -> String {
+    let mut tokens = Vec::new();
+    for (cjk, chars) in runs(text) {
+        if cjk {
+            for pair in chars.windows(2) {
+                tokens.push(pair.iter().collect::<String>());
+            }
+            tokens.push(chars[chars.len() - 1].to_string());
+        } else {
+            tokens.push(chars.into_iter().collect());
+        }
+    }
+    tokens.join(" ")
+}
+
+/// 1つの検索語をFTS5のフレーズに変換する。
+fn phrase(term: &str) -> Option<String> {
+    let runs = runs(term);
+    let last = runs.len().checked_sub(1)?;
+    let mut tokens = Vec::new();
+    let mut prefix = false;
+    for (i, (cjk, chars)) in runs.into_iter().enumerate() {
+        if !cjk {
+            tokens.push(chars.into_iter().collect());
+        } else if chars.len() == 1 {
+            tokens.push(chars[0].to_string());
+            prefix = i == last;
+        } else {
+            for pair in chars.windows(2) {
+                tokens.push(pair.iter().collect::<String>());
+            }
+            if i != last {
+                tokens.push(chars[chars.len() - 1].to_string());
+            }
+        }
+    }
+    Some(format!(
+        "\"{}\"{}",
+        tokens.join(" "),
+        if prefix { "*" } else { "" }
+    ))
+}
+
+/// 検索語をFTS5のクエリーに変換する。
+pub fn match_query(query: &str, mode: MatchMode) -> Option<String> {
+    let phrases = query
+        .split_whitespace()
+        .filter_map(phrase)
+        .collect::<Vec<_>>();
+    if phrases.is_empty() {
+        return None;
+    }
+    let operator = match mode {
+        MatchMode::All => " AND ",
+        MatchMode::Any => " OR ",
+    };
+    Some(phrases.join(operator))
+}
+
+/// レコードを全文検索の索引に登録する。登録済みの場合は置き換える。
+pub(super) fn index(conn: &Connection, doc: SearchDocument) -> anyhow::Result<()> {
+    let ty = doc.ty.to_string();
+    let rowid: Option<i64> = conn
+        .query_row(
+            "SELECT rowid FROM search_doc WHERE type = ?1 AND id = ?2",
+            params![ty, doc.id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let rowid = match rowid {
+        Some(rowid) => {
+            conn.execute("DELETE FROM search_index WHERE rowid = ?1", params![rowid])?;
+            rowid
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO search_doc (type, id) VALUES (?1, ?2)",
+                params![ty, doc.id],
+            )?;
+            conn.last_insert_rowid()
+        }
+    };
+    conn.execute(
+        "INSERT INTO search_index (rowid, title, answer, guide, outline, keywords) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            rowid,
+            tokenize(doc.title),
+            doc.answer.map(tokenize),
+            doc.guide.map(tokenize),
+            doc.outline.map(tokenize),
+            doc.keywords.map(|k| tokenize(&k.join(" "))),
+        ],
+    )?;
+    Ok(())
+}
+
+/// 保存済みのレコードから全文検索の索引を作り直す。
+pub(super) fn rebuild_index(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch("DELETE FROM search_index; DELETE FROM search_doc;")?;
+    let sources = [
+        (
+            ReqType::Reference,
+            "SELECT sys_id, question, answer, NULL, NULL, keywords FROM reference",
+        ),
+        (
+            ReqType::Manual,
+            "SELECT sys_id, theme, NULL, guide, NULL, keywords FROM manual",
+        ),
+        (
+            ReqType::Collection,
+            "SELECT sys_id, col_name, NULL, NULL, outline, keywords FROM collection",
+        ),
+    ];
+    for (ty, sql) in sources {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+            ))
+        })?;
+        for row in rows {
+            let (id, title, answer, guide, outline, keywords) = row?;
+            let keywords: Option<Vec<String>> =
+                keywords.map(|k| serde_json::from_str(&k)).transpose()?;
+            index(
+                conn,
+                SearchDocument {
+                    ty,
+                    id: &id,
+                    title: &title,
+                    answer: answer.as_deref(),
+                    guide: guide.as_deref(),
+                    outline: outline.as_deref(),
+                    keywords: keywords.as_deref(),
+                },
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// ローカルミラーを全文検索し、BM25の関連度の高い順に返す。
+pub fn search(
+    conn: &Connection,
+    request: &LocalSearchRequest,
+) -> anyhow::Result<LocalSearchResponse> {
+    let Some(query) = match_query(&request.query, request.mode) else {
+        anyhow::bail!("検索語を指定してください");
+    };
+    let ty = match request.ty {
+        None | Some(ReqType::All) => None,
+        Some(ReqType::Profile) => anyhow::bail!("参加館プロファイルは全文検索の対象外です"),
+        Some(ty) => Some(ty.to_string()),
+    };
+
+    let hit_count: i64 = conn.query_row(
+        "SELECT count(*) FROM search_index JOIN search_doc d ON d.rowid = search_index.rowid \
+         WHERE search_index MATCH ?1 AND (?2 IS NULL OR d.type = ?2)",
+        params![query, ty],
+        |row| row.get(0),
+    )?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT d.type, d.id, -bm25(search_index, {}) AS score \
+         FROM search_index JOIN search_doc d ON d.rowid = search_index.rowid \
+         WHERE search_index MATCH ?1 AND (?2 IS NULL OR d.type = ?2) \
+         ORDER BY score DESC LIMIT ?3",
+        BM25_WEIGHTS
+    ))?;
+    let hits = stmt
+        .query_map(params![query, ty, request.limit], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut results = Vec::with_capacity(hits.len());
+    for (ty, id, score) in hits {
+        // 種類はsearch_docに登録した値のみのため、テーブル名として安全に使用できる
+        let raw_xml: String = conn.query_row(
+            &format!("SELECT raw_xml FROM {} WHERE sys_id = ?1", ty),
+            params![id],
+            |row| row.get(0),
+        )?;
+        results.push(LocalSearchHit {
+            score,
+            record: parse_result(&raw_xml)?.into(),
+        });
+    }
+    Ok(LocalSearchResponse { hit_count, results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{match_query, tokenize};
+    use crate::req::MatchMode;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("北海道の地名"), "北海 海道 道の の地 地名 名");
+        assert_eq!(tokenize("ＮＤＣ分類"), "ndc 分類 類");
+        assert_eq!(tokenize("川"), "川");
+    }
+
+    #[test]
+    fn test_match_query() {
+        assert_eq!(
+            match_query("北海道 地名", MatchMode::All).unwrap(),
+            "\"北海 海道\" AND \"地名\""
+        );
+        assert_eq!(
+            match_query("川 NDC分類", MatchMode::Any).unwrap(),
+            "\"川\"* OR \"ndc 分類\""
+        );
+        assert_eq!(match_query("「」", MatchMode::All), None);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::req::{BrowseNdcRequest, Condition, CrdSearchRequest, ReqType};
+use crate::res::{BrowseNdcResponse, CrdSearchResponse, NdcNode};
+use crate::service::CrdService;
+use rmcp::ErrorData;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// NDC新訂9版の類目・綱目・要目の見出し
+///
+/// 1桁が類、2桁が綱、3桁が要目の記号で、欠番の記号は含まない。
+const NDC9_TABLE: &str = include_str!("ndc/ndc9.tsv");
+
+/// 新訂8版で9版と見出しが異なる記号
+///
+/// 8版・10版の見出しは、9版の表にこれらの違いのみを反映した近似である。
+const NDC8_CHANGES: &[(&str, &str)] = &[("378", "特殊教育")];
+
+/// 新訂10版で9版と見出しが異なる記号
+const NDC10_CHANGES: &[(&str, &str)] = &[
+    ("007", "情報学．情報科学"),
+    ("01", "図書館．図書館情報学"),
+    ("010", "図書館．図書館情報学"),
+    ("378", "障害児教育［特別支援教育］"),
+    ("54", "電気工学"),
+    ("540", "電気工学"),
+];
+
+static NDC9: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(|| {
+    NDC9_TABLE
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('\t'))
+        .collect()
+});
+
+/// NDCの版
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NdcEdition {
+    /// 新訂8版
+    #[serde(rename = "8")]
+    Ndc8,
+    /// 新訂9版
+    #[serde(rename = "9")]
+    Ndc9,
+    /// 新訂10版
+    #[default]
+    #[serde(rename = "10")]
+    Ndc10,
+}
+
+impl NdcEdition {
+    /// CRDの分類のバージョン(`9` など)から版を判定する。8〜10版以外は `None` を返す。
+    pub fn from_version(version: Option<&str>) -> Option<NdcEdition> {
+        match version.map(str::trim) {
+            Some("8") => Some(NdcEdition::Ndc8),
+            Some("9") => Some(NdcEdition::Ndc9),
+            Some("10") => Some(NdcEdition::Ndc10),
+            _ => None,
+        }
+    }
+
+    /// 見出しが9版の表からの近似である場合は `true`
+    pub fn is_approximate(self) -> bool {
+        self != NdcEdition::Ndc9
+    }
+
+    fn changes(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            NdcEdition::Ndc8 => NDC8_CHANGES,
+            NdcEdition::Ndc9 => &[],
+            NdcEdition::Ndc10 => NDC10_CHANGES,
+        }
+    }
+}
+
+/// 類・綱・要目の記号の見出し。表にない記号は `None` を返す。
+pub fn label(edition: NdcEdition, code: &str) -> Option<&'static str> {
+    edition
+        .changes()
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, label)| *label)
+        .or_else(|| NDC9.get(code).copied())
+}
+
+/// 分類記号を類・綱・要目の記号に分ける。`291.1` は `2`, `29`, `291` となる。
+fn levels(value: &str) -> Vec<&str> {
+    let value = value.trim();
+    let integer = value.split('.').next().unwrap_or_default();
+    if integer.is_empty() || !integer.chars().all(|c| c.is_ascii_digit()) {
+        return Vec::new();
+    }
+    (1..=integer.len().min(3)).map(|i| &integer[..i]).collect()
+}
+
+/// 類から要目までの見出しを ` > ` でつないだ表示用の見出し
+///
+/// `210` の綱と要目のように同じ見出しが続く場合は1つにまとめる。
+pub fn describe(edition: NdcEdition, value: &str) -> Option<String> {
+    let mut labels: Vec<&str> = Vec::new();
+    for code in levels(value) {
+        if let Some(label) = label(edition, code)
+            && labels.last() != Some(&label)
+        {
+            labels.push(label);
+        }
+    }
+    (!labels.is_empty()).then(|| labels.join(" > "))
+}
+
+/// 下位の区分の記号と見出し。空文字列は類を、1桁の記号は綱を、2桁の記号は要目を返す。
+pub fn children(edition: NdcEdition, code: &str) -> Vec<(String, &'static str)> {
+    if code.len() > 2 || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Vec::new();
+    }
+    (0..10)
+        .map(|i| format!("{}{}", code, i))
+        .filter_map(|child| label(edition, &child).map(|label| (child, label)))
+        .collect()
+}
+
+/// 下位の区分ごとに、`ndc` の前方一致検索でヒット件数を数える。
+///
+/// 下位の区分ごとにCRD APIを1回ずつ、最大10回呼び出す。呼び出しはRateLimiterの間隔で順に行う。
+pub async fn browse(
+    service: &CrdService,
+    request: BrowseNdcRequest,
+) -> Result<BrowseNdcResponse, ErrorData> {
+    let BrowseNdcRequest {
+        code,
+        edition,
+        ty,
+        lib_id,
+        lib_group,
+    } = request;
+    let code = code.unwrap_or_default().trim().to_string();
+    if code.len() > 2 || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(ErrorData::invalid_params(
+            format!(
+                "codeには類(1桁)または綱(2桁)の記号を指定してください。要目より下位の区分は収録していません: {}",
+                code
+            ),
+            None,
+        ));
+    }
+    if matches!(ty, ReqType::Profile | ReqType::All) {
+        return Err(ErrorData::invalid_params(
+            format!(
+                "NDCで検索できるのはreference・manual・collectionです: {}",
+                ty
+            ),
+            None,
+        ));
+    }
+
+    let mut nodes = Vec::new();
+    for (child, label) in children(edition, &code) {
+        let request = CrdSearchRequest {
+            ty,
+            condition: Condition {
+                query: Some(format!("ndc = {}", child)),
+                crt_date_from: None,
+                crt_date_to: None,
+                reg_date_from: None,
+                reg_date_to: None,
+                lst_date_from: None,
+                lst_date_to: None,
+            },
+            lib_id: lib_id.clone(),
+            lib_group,
+            lib_type: None,
+            results_get_position: None,
+            results_num: 1,
+        };
+        let k = service
+            .crd_search(request)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let i = Result::<CrdSearchResponse, ErrorData>::from(k)?;
+        nodes.push(NdcNode {
+            code: child,
+            label: label.to_string(),
+            hit_count: i.hit_count,
+        });
+    }
+    Ok(BrowseNdcResponse {
+        label: describe(edition, &code),
+        code,
+        edition,
+        approximate: edition.is_approximate(),
+        children: nodes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NdcEdition, children, describe, label};
+
+    #[test]
+    fn test_label() {
+        assert_eq!(label(NdcEdition::Ndc9, "2"), Some("歴史"));
+        assert_eq!(label(NdcEdition::Ndc9, "21"), Some("日本史"));
+        assert_eq!(label(NdcEdition::Ndc9, "211"), Some("北海道地方"));
+        assert_eq!(label(NdcEdition::Ndc9, "007"), Some("情報科学"));
+        assert_eq!(label(NdcEdition::Ndc10, "007"), Some("情報学．情報科学"));
+        assert_eq!(label(NdcEdition::Ndc9, "819"), None);
+        assert_eq!(label(NdcEdition::Ndc9, "933"), Some("小説．物語"));
+    }
+
+    #[test]
+    fn test_edition() {
+        assert_eq!(NdcEdition::from_version(Some("9")), Some(NdcEdition::Ndc9));
+        assert_eq!(
+            NdcEdition::from_version(Some(" 10")),
+            Some(NdcEdition::Ndc10)
+        );
+        assert_eq!(NdcEdition::from_version(Some("7")), None);
+        assert_eq!(NdcEdition::from_version(None), None);
+        assert!(!NdcEdition::Ndc9.is_approximate());
+        assert!(NdcEdition::Ndc10.is_approximate());
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe(NdcEdition::Ndc9, "291.1").unwrap(),
+            "歴史 > 地理．地誌．紀行 > 日本"
+        );
+        assert_eq!(describe(NdcEdition::Ndc10, "210").unwrap(), "歴史 > 日本史");
+        assert_eq!(describe(NdcEdition::Ndc10, "K29"), None);
+    }
+
+    #[test]
+    fn test_children() {
+        let classes = children(NdcEdition::Ndc10, "");
+        assert_eq!(classes.len(), 10);
+        assert_eq!(classes[9], ("9".to_string(), "文学"));
+
+        let sections = children(NdcEdition::Ndc10, "22");
+        assert_eq!(sections.len(), 9);
+        assert!(!sections.iter().any(|(code, _)| code == "226"));
+        assert!(children(NdcEdition::Ndc10, "211").is_empty());
+    }
+}
@@ -0,0 +1,728 @@
+use crate::library::quote;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use unicode_normalization::UnicodeNormalization;
+
+/// 旧字体・異体字と新字体の対応
+const JITAI_TABLE: &str = include_str!("query/jitai.tsv");
+
+/// 送り仮名・外来語などの表記ゆれ
+const VARIANTS_TABLE: &str = include_str!("query/variants.tsv");
+
+/// 1語あたりの展開する表記の最大数
+const MAX_VARIANTS: usize = 16;
+
+/// 展開しないクエリー対象項目。コード値・番号で検索する項目は表記ゆれがない。
+const LITERAL_INDEXES: &[&str] = &[
+    "reg-id",
+    "sys-id",
+    "ndc",
+    "solution",
+    "completion",
+    "continue",
+    "lib-type",
+    "bibl-isbn",
+    "isil",
+    "number",
+];
+
+/// 項目を指定した検索句を `anywhere` に広げる際に、広げない項目
+const NARROW_INDEXES: &[&str] = &["anywhere", "lib-name"];
+
+fn is_literal(index: &str) -> bool {
+    LITERAL_INDEXES.contains(&index.to_ascii_lowercase().as_str())
+}
+
+/// 旧字体から新字体、新字体から旧字体への対応
+static JITAI: LazyLock<(HashMap<char, char>, HashMap<char, char>)> = LazyLock::new(|| {
+    let mut old_to_new = HashMap::new();
+    let mut new_to_old = HashMap::new();
+    for fields in table_lines(JITAI_TABLE) {
+        let [old, new] = fields.as_slice() else {
+            continue;
+        };
+        let (Some(old), Some(new)) = (old.chars().next(), new.chars().next()) else {
+            continue;
+        };
+        old_to_new.insert(old, new);
+        new_to_old.entry(new).or_insert(old);
+    }
+    (old_to_new, new_to_old)
+});
+
+/// 表の各行をタブまたはカンマで区切る。空行と `#` で始まる行は読み飛ばす。
+pub(crate) fn table_lines(table: &str) -> impl Iterator<Item = Vec<&str>> {
+    table
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.split(['\t', ','])
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .collect()
+        })
+}
+
+fn is_kana(c: char) -> bool {
+    matches!(c, '\u{3041}'..='\u{3096}' | '\u{30a1}'..='\u{30f6}' | 'ー')
+}
+
+fn to_katakana(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '\u{3041}'..='\u{3096}' => char::from_u32(c as u32 + 0x60).unwrap_or(c),
+            c => c,
+        })
+        .collect()
+}
+
+fn to_hiragana(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '\u{30a1}'..='\u{30f6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            c => c,
+        })
+        .collect()
+}
+
+/// 英数字・記号を全角にする。
+fn to_fullwidth(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '!'..='~' => char::from_u32(c as u32 + 0xfee0).unwrap_or(c),
+            c => c,
+        })
+        .collect()
+}
+
+fn to_new_jitai(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| JITAI.0.get(&c).copied().unwrap_or(c))
+        .collect()
+}
+
+fn to_old_jitai(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| JITAI.1.get(&c).copied().unwrap_or(c))
+        .collect()
+}
+
+/// 辞書を引くための表記。全角・半角、旧字体・新字体、大文字・小文字の違いを無視する。
+pub fn normalize(value: &str) -> String {
+    to_new_jitai(&value.nfkc().collect::<String>()).to_lowercase()
+}
+
+/// CQLの関係演算子
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Relation {
+    /// いずれかの語を含む(OR)
+    #[serde(rename = "any")]
+    Any,
+    /// 全ての語を含む(AND)
+    #[default]
+    #[serde(rename = "all")]
+    All,
+    /// 一致。空白を含む場合はフレーズでの一致となる。
+    #[serde(rename = "=")]
+    Eq,
+}
+
+impl Display for Relation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Relation::Any => write!(f, "any"),
+            Relation::All => write!(f, "all"),
+            Relation::Eq => write!(f, "="),
+        }
+    }
+}
+
+/// ヒットしなかった検索式の緩和の方法
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Relaxation {
+    /// `=` の検索句を `any` にする
+    EqToAny,
+    /// 項目を指定した検索句を `anywhere` にする
+    Anywhere,
+    /// 最後の `and`・`not` の検索句を除く
+    DropClause,
+}
+
+/// CQLの論理演算子
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoolOp {
+    And,
+    Or,
+    Not,
+}
+
+impl Display for BoolOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoolOp::And => write!(f, "and"),
+            BoolOp::Or => write!(f, "or"),
+            BoolOp::Not => write!(f, "not"),
+        }
+    }
+}
+
+/// CQLの検索式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// `question any 北海道` などの検索句
+    Clause {
+        index: String,
+        relation: Relation,
+        value: String,
+    },
+    /// クエリー対象項目を指定しない検索語
+    Term(String),
+    /// 2つの検索式の論理演算
+    Bool {
+        op: BoolOp,
+        left: Box<Query>,
+        right: Box<Query>,
+    },
+}
+
+impl Query {
+    pub fn and(self, other: Query) -> Query {
+        Query::bool(BoolOp::And, self, other)
+    }
+
+    fn bool(op: BoolOp, left: Query, right: Query) -> Query {
+        Query::Bool {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// 各検索句を `f` で書き換える。`f` が `None` を返す検索句はそのままとする。
+    ///
+    /// `not` の右側の検索句は、広げると除外するレコードが増えるため書き換えない。
+    fn rewrite_clauses(&self, f: &impl Fn(&str, Relation, &str) -> Option<Query>) -> Query {
+        match self {
+            Query::Clause {
+                index,
+                relation,
+                value,
+            } => f(index, *relation, value).unwrap_or_else(|| self.clone()),
+            Query::Term(_) => self.clone(),
+            Query::Bool {
+                op: BoolOp::Not,
+                left,
+                right,
+            } => Query::bool(BoolOp::Not, left.rewrite_clauses(f), right.as_ref().clone()),
+            Query::Bool { op, left, right } => {
+                Query::bool(*op, left.rewrite_clauses(f), right.rewrite_clauses(f))
+            }
+        }
+    }
+
+    /// 検索式を1段階緩和する。緩和できない場合は `None` を返す。
+    ///
+    /// `=` の検索句を `any` にする、項目を指定した検索句を `anywhere` にする、
+    /// 最後の `and`・`not` の検索句を除く、の順に試す。コード値・番号で検索する項目と、`not` で除外する検索句は変えない。
+    pub fn relax(&self) -> Option<(Relaxation, Query)> {
+        let any = self.rewrite_clauses(&|index, relation, value| {
+            (relation == Relation::Eq && !is_literal(index)).then(|| Query::Clause {
+                index: index.to_string(),
+                relation: Relation::Any,
+                value: value.to_string(),
+            })
+        });
+        if any != *self {
+            return Some((Relaxation::EqToAny, any));
+        }
+        let anywhere = self.rewrite_clauses(&|index, relation, value| {
+            let narrow = NARROW_INDEXES.contains(&index.to_ascii_lowercase().as_str());
+            (!narrow && !is_literal(index)).then(|| Query::Clause {
+                index: "anywhere".to_string(),
+                relation,
+                value: value.to_string(),
+            })
+        });
+        if anywhere != *self {
+            return Some((Relaxation::Anywhere, anywhere));
+        }
+        match self {
+            Query::Bool {
+                op: BoolOp::And | BoolOp::Not,
+                left,
+                ..
+            } => Some((Relaxation::DropClause, left.as_ref().clone())),
+            _ => None,
+        }
+    }
+}
+
+/// 論理演算子は左結合とし、演算子の異なる検索式と右側のnotの検索式は括弧で囲む。
+impl Display for Query {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Query::Clause {
+                index,
+                relation,
+                value,
+            } => write!(f, "{index} {relation} {}", quote(value)),
+            Query::Term(term) => write!(f, "{}", quote(term)),
+            Query::Bool { op, left, right } => {
+                match left.as_ref() {
+                    Query::Bool { op: inner, .. } if inner != op => write!(f, "({left})")?,
+                    _ => write!(f, "{left}")?,
+                }
+                write!(f, " {op} ")?;
+                match right.as_ref() {
+                    Query::Bool { op: inner, .. } if inner == op && *op != BoolOp::Not => {
+                        write!(f, "{right}")
+                    }
+                    Query::Bool { .. } => write!(f, "({right})"),
+                    _ => write!(f, "{right}"),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Eq,
+    Word(String),
+    Quoted(String),
+}
+
+impl Token {
+    fn relation(&self) -> Option<Relation> {
+        match self {
+            Token::Eq => Some(Relation::Eq),
+            Token::Word(word) if word.eq_ignore_ascii_case("any") => Some(Relation::Any),
+            Token::Word(word) if word.eq_ignore_ascii_case("all") => Some(Relation::All),
+            _ => None,
+        }
+    }
+
+    fn bool_op(&self) -> Option<BoolOp> {
+        match self {
+            Token::Word(word) if word.eq_ignore_ascii_case("and") => Some(BoolOp::And),
+            Token::Word(word) if word.eq_ignore_ascii_case("or") => Some(BoolOp::Or),
+            Token::Word(word) if word.eq_ignore_ascii_case("not") => Some(BoolOp::Not),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Eq => write!(f, "="),
+            Token::Word(word) => write!(f, "{word}"),
+            Token::Quoted(value) => write!(f, "\"{value}\""),
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '=' => tokens.push(Token::Eq),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some(c) => value.push(c),
+                        None => return Err("検索式の引用符が閉じられていません".to_string()),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '=' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_query(&mut self) -> Result<Query, String> {
+        let mut query = self.parse_operand()?;
+        while let Some(op) = self.peek().and_then(Token::bool_op) {
+            self.position += 1;
+            let right = self.parse_operand()?;
+            query = Query::bool(op, query, right);
+        }
+        Ok(query)
+    }
+
+    fn parse_operand(&mut self) -> Result<Query, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let query = self.parse_query()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err("検索式の括弧が閉じられていません".to_string()),
+                }
+            }
+            Some(Token::Quoted(value)) => Ok(Query::Term(value)),
+            Some(Token::Word(word)) => {
+                let Some(relation) = self.peek().and_then(Token::relation) else {
+                    return Ok(Query::Term(word));
+                };
+                self.position += 1;
+                let value = match self.next() {
+                    Some(Token::Quoted(value)) => value,
+                    // `question any 本 音楽` のように引用符のない複数の語を続けて書ける。
+                    Some(Token::Word(value)) if relation != Relation::Eq => {
+                        let mut terms = vec![value];
+                        while let Some(Token::Word(term)) = self.peek() {
+                            if self.peek().and_then(Token::bool_op).is_some() {
+                                break;
+                            }
+                            terms.push(term.clone());
+                            self.position += 1;
+                        }
+                        terms.join(" ")
+                    }
+                    Some(Token::Word(value)) => value,
+                    _ => {
+                        return Err(format!(
+                            "検索式の「{word} {relation}」の後に検索語がありません"
+                        ));
+                    }
+                };
+                Ok(Query::Clause {
+                    index: word,
+                    relation,
+                    value,
+                })
+            }
+            Some(token) => Err(format!("検索式の「{token}」の位置が正しくありません")),
+            None => Err("検索式が途中で終わっています".to_string()),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let query = parser.parse_query()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("検索式の「{token}」の位置が正しくありません"));
+        }
+        Ok(query)
+    }
+}
+
+/// 検索語を表記ゆれ・同義語に展開する。
+///
+/// 旧字体・新字体、ひらがな・カタカナ、全角・半角の違いは文字の対応で、
+/// 送り仮名などの表記ゆれと同義語は辞書で展開する。
+#[derive(Debug, Clone, Default)]
+pub struct Expander {
+    /// 正規化した表記から、同じ語の表記の一覧の位置
+    index: HashMap<String, usize>,
+    groups: Vec<Vec<String>>,
+}
+
+impl Expander {
+    /// 同梱の表記ゆれの辞書を読み込む。
+    pub fn bundled() -> Expander {
+        let mut expander = Expander::default();
+        expander.add_groups(VARIANTS_TABLE);
+        expander
+    }
+
+    /// 利用者の同義語辞書を追加する。
+    ///
+    /// 1行に同じ意味の語をタブまたはカンマ区切りで並べる。`#` で始まる行は注釈とする。
+    pub fn with_dictionary(mut self, path: &Path) -> anyhow::Result<Expander> {
+        let table = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("同義語辞書 {} を読み込めません: {e}", path.display()))?;
+        self.add_groups(&table);
+        Ok(self)
+    }
+
+    fn add_groups(&mut self, table: &str) {
+        for terms in table_lines(table) {
+            // 既に登録された語を含む場合は、その語の表記の一覧に加える。
+            let group = match terms
+                .iter()
+                .find_map(|term| self.index.get(&normalize(term)).copied())
+            {
+                Some(group) => group,
+                None => {
+                    self.groups.push(Vec::new());
+                    self.groups.len() - 1
+                }
+            };
+            for term in terms {
+                if !self.groups[group].iter().any(|t| t == term) {
+                    self.groups[group].push(term.to_string());
+                }
+                self.index.insert(normalize(term), group);
+            }
+        }
+    }
+
+    /// 語の表記ゆれと同義語の一覧。最初の要素は元の語とする。
+    pub fn variants(&self, term: &str) -> Vec<String> {
+        let synonyms = self
+            .index
+            .get(&normalize(term))
+            .map(|&group| self.groups[group].as_slice())
+            .unwrap_or_default();
+        let mut variants = vec![term.to_string()];
+        for word in std::iter::once(term).chain(synonyms.iter().map(String::as_str)) {
+            let mut forms = vec![
+                word.to_string(),
+                word.nfkc().collect(),
+                to_fullwidth(word),
+                to_new_jitai(word),
+                to_old_jitai(word),
+            ];
+            if word.chars().all(is_kana) {
+                forms.extend([to_katakana(word), to_hiragana(word)]);
+            }
+            for form in forms {
+                if variants.len() < MAX_VARIANTS && !form.is_empty() && !variants.contains(&form) {
+                    variants.push(form);
+                }
+            }
+        }
+        variants
+    }
+
+    /// 空白を含まない表記ゆれ。`any`・`all` の検索語は空白で区切るため、空白を含む同義語は除く。
+    fn word_variants(&self, term: &str) -> Vec<String> {
+        self.variants(term)
+            .into_iter()
+            .filter(|variant| !variant.contains(char::is_whitespace))
+            .collect()
+    }
+
+    /// 検索式の各語を表記ゆれ・同義語の `any` のグループに展開する。
+    ///
+    /// - `any` は各語の表記をすべて同じ `any` の検索句に含める。
+    /// - `all` は表記ゆれのある語を `any` の検索句として `and` で結合する。
+    /// - `=` は語句の表記ごとの検索句を `or` で結合する。
+    ///
+    /// コード値・番号で検索する項目と、クエリー対象項目のない検索語は展開しない。
+    pub fn expand(&self, query: &Query) -> Query {
+        let (index, relation, value) = match query {
+            Query::Bool { op, left, right } => {
+                return Query::bool(*op, self.expand(left), self.expand(right));
+            }
+            Query::Term(_) => return query.clone(),
+            Query::Clause { index, .. } if is_literal(index) => return query.clone(),
+            Query::Clause {
+                index,
+                relation,
+                value,
+            } => (index, *relation, value),
+        };
+        let clause = |relation, value: String| Query::Clause {
+            index: index.clone(),
+            relation,
+            value,
+        };
+        match relation {
+            Relation::Any => {
+                let mut terms: Vec<String> = Vec::new();
+                for variant in value
+                    .split_whitespace()
+                    .flat_map(|term| self.word_variants(term))
+                {
+                    if !terms.contains(&variant) {
+                        terms.push(variant);
+                    }
+                }
+                clause(Relation::Any, terms.join(" "))
+            }
+            Relation::All => {
+                let mut fixed = Vec::new();
+                let mut groups = Vec::new();
+                for term in value.split_whitespace() {
+                    let variants = self.word_variants(term);
+                    if variants.len() > 1 {
+                        groups.push(clause(Relation::Any, variants.join(" ")));
+                    } else {
+                        fixed.push(term);
+                    }
+                }
+                if groups.is_empty() {
+                    return query.clone();
+                }
+                (!fixed.is_empty())
+                    .then(|| clause(Relation::All, fixed.join(" ")))
+                    .into_iter()
+                    .chain(groups)
+                    .reduce(Query::and)
+                    .unwrap()
+            }
+            Relation::Eq => self
+                .variants(value)
+                .into_iter()
+                .map(|variant| clause(Relation::Eq, variant))
+                .reduce(|left, right| Query::bool(BoolOp::Or, left, right))
+                .unwrap(),
+        }
+    }
+
+    /// CQLの検索式を展開し、展開後の検索式を返す。
+    pub fn expand_cql(&self, query: &str) -> Result<String, String> {
+        Ok(self.expand(&query.parse()?).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expander, Query, Relation, Relaxation};
+
+    #[test]
+    fn test_parse() {
+        let query: Query = "question any 本 音楽 and solution = 0".parse().unwrap();
+        assert_eq!(
+            query.to_string(),
+            r#"question any "本 音楽" and solution = "0""#
+        );
+        let query: Query = r#"(question any "北海道" or answer all 札幌) not note=備考"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            query.to_string(),
+            r#"(question any "北海道" or answer all "札幌") not note = "備考""#
+        );
+        assert!("question any".parse::<Query>().is_err());
+        assert!("(question any 本".parse::<Query>().is_err());
+        assert!(r#"question any "本"#.parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn test_relax() {
+        let mut query: Query =
+            "anywhere = 池袋駅 and question = 雑司が谷 and ndc = 29 not note = 川"
+                .parse()
+                .unwrap();
+        let mut steps = Vec::new();
+        while let Some((relaxation, relaxed)) = query.relax() {
+            steps.push((relaxation, relaxed.to_string()));
+            query = relaxed;
+        }
+        assert_eq!(
+            steps,
+            [
+                (
+                    Relaxation::EqToAny,
+                    r#"(anywhere any "池袋駅" and question any "雑司が谷" and ndc = "29") not note = "川""#
+                        .to_string()
+                ),
+                (
+                    Relaxation::Anywhere,
+                    r#"(anywhere any "池袋駅" and anywhere any "雑司が谷" and ndc = "29") not note = "川""#
+                        .to_string()
+                ),
+                (
+                    Relaxation::DropClause,
+                    r#"anywhere any "池袋駅" and anywhere any "雑司が谷" and ndc = "29""#.to_string()
+                ),
+                (
+                    Relaxation::DropClause,
+                    r#"anywhere any "池袋駅" and anywhere any "雑司が谷""#.to_string()
+                ),
+                (Relaxation::DropClause, r#"anywhere any "池袋駅""#.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_variants() {
+        let expander = Expander::bundled();
+        assert_eq!(expander.variants("國學"), ["國學", "国学"]);
+        assert_eq!(expander.variants("りんご"), ["りんご", "リンゴ"]);
+        assert_eq!(expander.variants("ＣＤ"), ["ＣＤ", "CD"]);
+        assert_eq!(expander.variants("CD"), ["CD", "ＣＤ"]);
+        assert_eq!(expander.variants("取扱"), ["取扱", "取り扱い", "取扱い"]);
+        assert_eq!(expander.variants("北海道"), ["北海道"]);
+    }
+
+    #[test]
+    fn test_expand() {
+        let expander = Expander::bundled();
+        let expand = |query: &str| expander.expand_cql(query).unwrap();
+        assert_eq!(
+            expand("question any 国学 北海道"),
+            r#"question any "国学 國學 北海道""#
+        );
+        assert_eq!(
+            expand("question all 国学 北海道 or ndc = 21"),
+            r#"(question all "北海道" and question any "国学 國學") or ndc = "21""#
+        );
+        assert_eq!(
+            expand(r#"keyword = "りんご""#),
+            r#"keyword = "りんご" or keyword = "リンゴ""#
+        );
+        assert_eq!(expand("北海道"), r#""北海道""#);
+
+        let query = Query::Clause {
+            index: "anywhere".to_string(),
+            relation: Relation::All,
+            value: "子ども".to_string(),
+        };
+        assert_eq!(
+            expander.expand(&query).to_string(),
+            r#"anywhere any "子ども 子供 こども コドモ""#
+        );
+    }
+}
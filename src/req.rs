@@ -1,9 +1,19 @@
 mod condition;
 
+use crate::export::ExportFormat;
+use crate::export::bibliography::BibliographyFormat;
+use crate::isbn::Isbn;
+use crate::library::{normalize_prefecture, quote};
+use crate::ndc::NdcEdition;
+use crate::query::{Query, Relation};
 pub(crate) use crate::req::condition::Condition;
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use chrono::NaiveDate;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
 
 fn default_results_num() -> i8 {
     100
@@ -25,6 +35,11 @@ pub struct CrdSearchRequest {
     /// 指定がない場合は全ての館から検索を行う。
     #[serde(skip_serializing_if = "Option::is_none")]
     pub lib_group: Option<LibGroup>,
+    /// 館種。type = "profile" の場合のみ指定できる。
+    ///
+    /// queryの `lib-type` の検索句として追加する。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lib_type: Option<LibType>,
     /// 検索結果取得位置
     ///
     /// 検索結果の取得開始位置を0からのインデックスで指定する。
@@ -38,8 +53,273 @@ pub struct CrdSearchRequest {
     pub results_num: i8,
 }
 
-/// 検索対象
+impl CrdSearchRequest {
+    /// 検索条件の組み合わせを検証する。CRD APIを呼び出す前に、誤った指定を利用者に返すために使う。
+    pub fn validate(&self) -> Result<(), String> {
+        let Some(lib_type) = &self.lib_type else {
+            return Ok(());
+        };
+        if self.ty != ReqType::Profile {
+            return Err("lib_typeはtype = \"profile\"の場合のみ指定できます".to_string());
+        }
+        if self
+            .lib_group
+            .is_some_and(|group| !group.lib_types().contains(lib_type))
+        {
+            return Err(format!(
+                "館種 {} はlib_groupの図書館グループに含まれていません",
+                lib_type.label().unwrap_or(lib_type.code())
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 検索条件を省略し、cursorのみで続きを取得できるようにする。
+///
+/// 検索条件を `Option` としてflattenすると必須項目の指定が失われるため、
+/// cursorを指定しない場合の必須項目をここで指定し直す。queryの代わりにclausesを指定することもできる。
+fn search_or_cursor(schema: &mut Schema) {
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+    object.remove("required");
+    let mut conditions: Vec<_> = match object.remove("anyOf") {
+        Some(serde_json::Value::Array(conditions)) => conditions
+            .into_iter()
+            .filter(|c| c.as_object().is_some_and(|c| !c.is_empty()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    conditions.push(serde_json::json!({ "required": ["clauses"] }));
+    object.insert(
+        "anyOf".to_owned(),
+        serde_json::json!([
+            { "required": ["cursor"] },
+            { "required": ["type"], "anyOf": conditions },
+        ]),
+    );
+}
+
+/// 検索条件と検索結果の付加情報の指定
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+#[schemars(transform = search_or_cursor)]
+pub struct SearchRequest {
+    #[serde(flatten)]
+    pub search: Option<CrdSearchRequest>,
+    /// 前回の検索結果のnext_cursor。指定した場合は前回の検索条件の続きを取得するため、他の項目は省略できる。
+    ///
+    /// 他の検索条件を併せて指定する場合は、前回と同じ検索条件でなければならない。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// trueの場合、各レコードに提供館の参加館プロファイルから住所・電話番号・URL・ISILなどの連絡先を付与する。
+    ///
+    /// 提供館ごとに参加館プロファイルを検索するため、初回は時間がかかる。
+    #[serde(default)]
+    pub include_library: bool,
+    /// 並べ替えの基準。relevance以外を指定した場合は、先頭から最大1000件を取得して並べ替え、
+    /// results_get_positionからresults_num件を返す。指定がない場合はCRD APIの返却順とする。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_by: Option<SortKey>,
+    /// 並び順。デフォルトは日時は新しい順(desc)、提供館名は昇順(asc)。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<SortOrder>,
+    /// type = "all" の場合の種類ごとの検索結果返却件数。指定がない種類はresults_numとし、0の種類は検索しない。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub results_num_by_type: Option<ResultsNumByType>,
+    /// 検索式を組み立てるための検索句。複数指定した場合はandで結合し、queryを併せて指定した場合はqueryともandで結合する。
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub clauses: Vec<QueryClause>,
+    /// trueの場合、query・clausesの各語を旧字体・新字体、ひらがな・カタカナ、全角・半角、送り仮名などの表記ゆれと同義語に展開し、
+    /// いずれかの表記を含む(any)検索句とする。
+    ///
+    /// 展開した検索式はレスポンスのexpanded_queryに返す。
+    #[serde(default)]
+    pub expand: bool,
+    /// trueの場合、ヒットしなかった検索式を段階的に緩和して検索し直す。
+    ///
+    /// `=` の検索句を `any` に、項目を指定した検索句を `anywhere` にし、最後の `and`・`not` の検索句から順に除く。`not` で除外する検索句は変えない。
+    /// ヒットした検索式と各段階のヒット件数はレスポンスのrelaxationに返す。
+    #[serde(default)]
+    pub relax: bool,
+}
+
+fn default_index() -> String {
+    "anywhere".to_string()
+}
+
+/// 検索式を組み立てるための検索句
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct QueryClause {
+    /// クエリー対象項目(例 question)。デフォルトはanywhere。
+    #[serde(default = "default_index")]
+    pub index: String,
+    /// 関係演算子。デフォルトはall。
+    #[serde(default)]
+    pub relation: Relation,
+    /// 検索語。relationが = の場合は空白で結合して1つの語句とする。
+    #[schemars(length(min = 1))]
+    pub terms: Vec<String>,
+}
+
+impl From<&QueryClause> for Query {
+    fn from(clause: &QueryClause) -> Query {
+        Query::Clause {
+            index: clause.index.clone(),
+            relation: clause.relation,
+            value: clause.terms.join(" "),
+        }
+    }
+}
+
+/// type = "all" の場合の種類ごとの検索結果返却件数
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ResultsNumByType {
+    /// レファレンス事例
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0, max = 100))]
+    pub reference: Option<i8>,
+    /// 調べ方マニュアル
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0, max = 100))]
+    pub manual: Option<i8>,
+    /// 特別コレクション
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0, max = 100))]
+    pub collection: Option<i8>,
+    /// 参加館プロファイル
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0, max = 100))]
+    pub profile: Option<i8>,
+}
+
+impl ResultsNumByType {
+    pub fn get(&self, ty: ReqType) -> Option<i8> {
+        match ty {
+            ReqType::Reference => self.reference,
+            ReqType::Manual => self.manual,
+            ReqType::Collection => self.collection,
+            ReqType::Profile => self.profile,
+            ReqType::All => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SearchRequest {
+    /// `cursor` のみを指定した場合は、検索条件を `None` とする。
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut map = serde_json::Map::<String, serde_json::Value>::deserialize(deserializer)?;
+        fn take<T: de::DeserializeOwned, E: de::Error>(
+            map: &mut serde_json::Map<String, serde_json::Value>,
+            key: &str,
+        ) -> Result<Option<T>, E> {
+            match map.remove(key) {
+                None | Some(serde_json::Value::Null) => Ok(None),
+                Some(value) => serde_json::from_value(value).map(Some).map_err(E::custom),
+            }
+        }
+        let cursor: Option<String> = take(&mut map, "cursor")?;
+        let include_library = take(&mut map, "include_library")?.unwrap_or(false);
+        let sort_by = take(&mut map, "sort_by")?;
+        let order = take(&mut map, "order")?;
+        let results_num_by_type = take(&mut map, "results_num_by_type")?;
+        let clauses: Vec<QueryClause> = take(&mut map, "clauses")?.unwrap_or_default();
+        let expand = take(&mut map, "expand")?.unwrap_or(false);
+        let relax = take(&mut map, "relax")?.unwrap_or(false);
+        // 検索句はqueryとandで結合し、queryとして検索する。
+        if let Some(clauses) = clauses.iter().map(Query::from).reduce(Query::and) {
+            let query = match take::<String, D::Error>(&mut map, "query")? {
+                Some(query) => query
+                    .parse::<Query>()
+                    .map_err(de::Error::custom)?
+                    .and(clauses),
+                None => clauses,
+            };
+            map.insert("query".to_owned(), query.to_string().into());
+        }
+        let search = if cursor.is_some() && map.is_empty() {
+            None
+        } else {
+            Some(
+                CrdSearchRequest::deserialize(serde_json::Value::Object(map))
+                    .map_err(de::Error::custom)?,
+            )
+        };
+        Ok(SearchRequest {
+            search,
+            cursor,
+            include_library,
+            sort_by,
+            order,
+            results_num_by_type,
+            clauses,
+            expand,
+            relax,
+        })
+    }
+}
+
+/// 複数の検索条件をまとめて検索する条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct MultiSearchRequest {
+    /// 検索条件の一覧。最大10件で、labelごとに検索結果を返す。
+    #[schemars(length(min = 1, max = 10))]
+    pub searches: Vec<LabeledSearchRequest>,
+    /// trueの場合、全ての検索結果をsys_idで重複を除いてまとめたmergedも返す。
+    #[serde(default)]
+    pub merge: bool,
+    /// trueの場合、各検索条件のqueryを表記ゆれ・同義語で展開する。
+    #[serde(default)]
+    pub expand: bool,
+}
+
+/// ラベルを付けた検索条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct LabeledSearchRequest {
+    /// 検索結果を識別するラベル(例 公共図書館)。検索条件の間で重複しないようにする。
+    pub label: String,
+    #[serde(flatten)]
+    pub search: CrdSearchRequest,
+}
+
+/// 検索結果の並べ替えの基準
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    /// 事例作成日・調べ方作成日。作成日のないレコードは末尾とする。
+    CreatedAt,
+    /// 登録日時
+    RegDate,
+    /// 最終更新日時
+    LstDate,
+    /// 提供館名
+    LibName,
+    /// CRD APIの返却順(並べ替えない)
+    Relevance,
+}
+
+impl SortKey {
+    /// 並び順の指定がない場合の並び順
+    pub fn default_order(self) -> SortOrder {
+        match self {
+            SortKey::LibName => SortOrder::Asc,
+            _ => SortOrder::Desc,
+        }
+    }
+}
+
+/// 並び順
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// 昇順(古い順)
+    Asc,
+    /// 降順(新しい順)
+    Desc,
+}
+
+/// 検索対象
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum ReqType {
     /// レファレンス事例
@@ -67,22 +347,38 @@ impl Display for ReqType {
     }
 }
 
-#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+impl FromStr for ReqType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reference" => Ok(ReqType::Reference),
+            "manual" => Ok(ReqType::Manual),
+            "collection" => Ok(ReqType::Collection),
+            "profile" => Ok(ReqType::Profile),
+            "all" => Ok(ReqType::All),
+            _ => Err(format!("unknown type: {}", s)),
+        }
+    }
+}
+
+/// 図書館グループ
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum LibGroup {
     /// 全館
     All,
-    /// 国立国会図書館
+    /// 国立国会図書館(館種コード11〜14)
     Ndl,
-    /// 公共図書館
+    /// 公共図書館(館種コード21〜24)
     Public,
-    /// 大学図書館
+    /// 大学図書館(館種コード31〜35)
     Academic,
-    /// 専門図書館
+    /// 専門図書館(館種コード41〜44)
     Special,
-    /// 学校図書館
+    /// 学校図書館(館種コード51〜54)
     School,
-    /// アーカイブズ
+    /// アーカイブズ(館種コード90)
     Archives,
 }
 
@@ -100,3 +396,962 @@ impl Display for LibGroup {
         write!(f, "{}", s)
     }
 }
+
+impl FromStr for LibGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(LibGroup::All),
+            "ndl" => Ok(LibGroup::Ndl),
+            "public" => Ok(LibGroup::Public),
+            "academic" => Ok(LibGroup::Academic),
+            "special" => Ok(LibGroup::Special),
+            "school" => Ok(LibGroup::School),
+            "archives" => Ok(LibGroup::Archives),
+            _ => Err(format!("unknown lib_group: {}", s)),
+        }
+    }
+}
+
+impl LibGroup {
+    pub const ALL: [LibGroup; 7] = [
+        LibGroup::All,
+        LibGroup::Ndl,
+        LibGroup::Public,
+        LibGroup::Academic,
+        LibGroup::Special,
+        LibGroup::School,
+        LibGroup::Archives,
+    ];
+
+    /// グループの名前(例 公共図書館)
+    pub fn label(&self) -> &'static str {
+        match self {
+            LibGroup::All => "全館",
+            LibGroup::Ndl => "国立国会図書館",
+            LibGroup::Public => "公共図書館",
+            LibGroup::Academic => "大学図書館",
+            LibGroup::Special => "専門図書館",
+            LibGroup::School => "学校図書館",
+            LibGroup::Archives => "アーカイブズ",
+        }
+    }
+
+    /// グループに含まれる館種。`All` は全ての館種を含む。
+    pub fn lib_types(&self) -> Vec<LibType> {
+        LibType::ALL
+            .into_iter()
+            .filter(|ty| matches!(self, LibGroup::All) || ty.group() == Some(*self))
+            .collect()
+    }
+}
+
+/// 館種(参加館プロファイルの図書館種別)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LibType {
+    /// 11 国立国会図書館(東京本館)
+    NdlTokyo,
+    /// 12 国立国会図書館(関西館)
+    NdlKansai,
+    /// 13 国立国会図書館(国際子ども図書館)
+    NdlChildren,
+    /// 14 国立国会図書館(支部図書館)
+    NdlBranch,
+    /// 21 公共図書館(都道府県立)
+    PublicPrefectural,
+    /// 22 公共図書館(政令指定都市立)
+    PublicDesignatedCity,
+    /// 23 公共図書館(市立・特別区立)
+    PublicCity,
+    /// 24 公共図書館(町村立)
+    PublicTown,
+    /// 31 大学図書館(国立大学)
+    AcademicNational,
+    /// 32 大学図書館(公立大学)
+    AcademicPublic,
+    /// 33 大学図書館(私立大学)
+    AcademicPrivate,
+    /// 35 大学図書館(高等専門)
+    AcademicTechnical,
+    /// 41 専門図書館(国公立)
+    SpecialGovernment,
+    /// 42 専門図書館(公益法人)
+    SpecialFoundation,
+    /// 43 専門図書館(企業)
+    SpecialCorporate,
+    /// 44 専門図書館(その他)
+    SpecialOther,
+    /// 51 学校図書館(高等学校)
+    SchoolHigh,
+    /// 52 学校図書館(中学校)
+    SchoolJunior,
+    /// 53 学校図書館(小学校)
+    SchoolElementary,
+    /// 54 学校図書館(その他)
+    SchoolOther,
+    /// 90 アーカイブズ
+    Archives,
+    /// 館種コード表にない館種コード。CRDが返した値をそのまま保持する。
+    Unknown(String),
+}
+
+impl LibType {
+    pub const ALL: [LibType; 21] = [
+        LibType::NdlTokyo,
+        LibType::NdlKansai,
+        LibType::NdlChildren,
+        LibType::NdlBranch,
+        LibType::PublicPrefectural,
+        LibType::PublicDesignatedCity,
+        LibType::PublicCity,
+        LibType::PublicTown,
+        LibType::AcademicNational,
+        LibType::AcademicPublic,
+        LibType::AcademicPrivate,
+        LibType::AcademicTechnical,
+        LibType::SpecialGovernment,
+        LibType::SpecialFoundation,
+        LibType::SpecialCorporate,
+        LibType::SpecialOther,
+        LibType::SchoolHigh,
+        LibType::SchoolJunior,
+        LibType::SchoolElementary,
+        LibType::SchoolOther,
+        LibType::Archives,
+    ];
+
+    /// 館種コードから変換する。館種コード表にないコードは `Unknown` とする。
+    pub fn from_code(code: &str) -> LibType {
+        let code = code.trim();
+        LibType::ALL
+            .into_iter()
+            .find(|ty| ty.code() == code)
+            .unwrap_or_else(|| LibType::Unknown(code.to_string()))
+    }
+
+    /// 館種コード
+    pub fn code(&self) -> &str {
+        match self {
+            LibType::NdlTokyo => "11",
+            LibType::NdlKansai => "12",
+            LibType::NdlChildren => "13",
+            LibType::NdlBranch => "14",
+            LibType::PublicPrefectural => "21",
+            LibType::PublicDesignatedCity => "22",
+            LibType::PublicCity => "23",
+            LibType::PublicTown => "24",
+            LibType::AcademicNational => "31",
+            LibType::AcademicPublic => "32",
+            LibType::AcademicPrivate => "33",
+            LibType::AcademicTechnical => "35",
+            LibType::SpecialGovernment => "41",
+            LibType::SpecialFoundation => "42",
+            LibType::SpecialCorporate => "43",
+            LibType::SpecialOther => "44",
+            LibType::SchoolHigh => "51",
+            LibType::SchoolJunior => "52",
+            LibType::SchoolElementary => "53",
+            LibType::SchoolOther => "54",
+            LibType::Archives => "90",
+            LibType::Unknown(code) => code,
+        }
+    }
+
+    /// 館種コードのデコード値。館種コード表にないコードは `None` を返す。
+    pub fn label(&self) -> Option<&'static str> {
+        match self {
+            LibType::NdlTokyo => Some("国立国会図書館(東京本館)"),
+            LibType::NdlKansai => Some("国立国会図書館(関西館)"),
+            LibType::NdlChildren => Some("国立国会図書館(国際子ども図書館)"),
+            LibType::NdlBranch => Some("国立国会図書館(支部図書館)"),
+            LibType::PublicPrefectural => Some("公共図書館(都道府県立)"),
+            LibType::PublicDesignatedCity => Some("公共図書館(政令指定都市立)"),
+            LibType::PublicCity => Some("公共図書館(市立・特別区立)"),
+            LibType::PublicTown => Some("公共図書館(町村立)"),
+            LibType::AcademicNational => Some("大学図書館(国立大学)"),
+            LibType::AcademicPublic => Some("大学図書館(公立大学)"),
+            LibType::AcademicPrivate => Some("大学図書館(私立大学)"),
+            LibType::AcademicTechnical => Some("大学図書館(高等専門)"),
+            LibType::SpecialGovernment => Some("専門図書館(国公立)"),
+            LibType::SpecialFoundation => Some("専門図書館(公益法人)"),
+            LibType::SpecialCorporate => Some("専門図書館(企業)"),
+            LibType::SpecialOther => Some("専門図書館(その他)"),
+            LibType::SchoolHigh => Some("学校図書館(高等学校)"),
+            LibType::SchoolJunior => Some("学校図書館(中学校)"),
+            LibType::SchoolElementary => Some("学校図書館(小学校)"),
+            LibType::SchoolOther => Some("学校図書館(その他)"),
+            LibType::Archives => Some("アーカイブズ"),
+            LibType::Unknown(_) => None,
+        }
+    }
+
+    /// 館種が属する図書館グループ。館種コード表にないコードは `None` を返す。
+    pub fn group(&self) -> Option<LibGroup> {
+        match self {
+            LibType::Unknown(_) => None,
+            ty => match ty.code().as_bytes()[0] {
+                b'1' => Some(LibGroup::Ndl),
+                b'2' => Some(LibGroup::Public),
+                b'3' => Some(LibGroup::Academic),
+                b'4' => Some(LibGroup::Special),
+                b'5' => Some(LibGroup::School),
+                _ => Some(LibGroup::Archives),
+            },
+        }
+    }
+}
+
+impl Display for LibType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// 館種コード(`21`)またはデコード値(`公共図書館(都道府県立)`)から変換する。
+///
+/// デコード値は全角・半角の括弧の違いを区別しない。館種コード表にない値はエラーとする。
+impl FromStr for LibType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().nfkc().collect::<String>();
+        LibType::ALL
+            .into_iter()
+            .find(|ty| ty.code() == normalized || ty.label() == Some(normalized.as_str()))
+            .ok_or_else(|| format!("unknown lib_type: {}", s))
+    }
+}
+
+impl Serialize for LibType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for LibType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+impl JsonSchema for LibType {
+    fn schema_name() -> Cow<'static, str> {
+        "LibType".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let values = LibType::ALL
+            .iter()
+            .flat_map(|ty| [Some(ty.code()), ty.label()])
+            .flatten()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        json_schema!({
+            "type": "string",
+            "description": "館種。館種コード(例 21)またはデコード値(例 公共図書館(都道府県立))で指定する。",
+            "enum": values,
+        })
+    }
+}
+
+fn default_max_records() -> u32 {
+    500
+}
+
+/// 新着・更新レコードの取得条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct RecentUpdatesRequest {
+    /// 検索対象を設定する。
+    #[serde(rename = "type")]
+    pub ty: ReqType,
+    /// 対象期間の開始日。YYYYMMDDで指定。
+    pub since: String,
+    /// 対象期間の終了日。YYYYMMDDで指定。指定がない場合は現在までを対象とする。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+    /// 期間を判定する日付。デフォルトは最終更新日。
+    #[serde(default)]
+    pub basis: RecentBasis,
+    /// 提供館コード
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_id: Option<String>,
+    /// 検索対象の図書館グループを指定する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_group: Option<LibGroup>,
+    /// 取得する最大件数
+    ///
+    /// 期間内のレコードをページングして取得する上限。デフォルトは500件。
+    #[serde(default = "default_max_records")]
+    #[schemars(range(min = 1, max = 2000))]
+    pub max_records: u32,
+}
+
+/// 新着・更新の判定に使う日付
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecentBasis {
+    /// 登録日
+    Registered,
+    /// 最終更新日(新規登録を含む)
+    #[default]
+    Updated,
+}
+
+/// 期間の日付(YYYYMMDD)を検証する。
+fn parse_yyyymmdd(name: &str, value: &str) -> Result<NaiveDate, String> {
+    let value = value.trim();
+    if value.len() != 8 || !value.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("{}はYYYYMMDDで指定してください: {}", name, value));
+    }
+    NaiveDate::parse_from_str(value, "%Y%m%d")
+        .map_err(|_| format!("{}の日付が正しくありません: {}", name, value))
+}
+
+impl TryFrom<RecentUpdatesRequest> for CrdSearchRequest {
+    type Error = String;
+
+    /// 期間の日付を検証し、`basis` に応じた登録日・最終更新日の検索条件に変換する。
+    fn try_from(value: RecentUpdatesRequest) -> Result<Self, Self::Error> {
+        let RecentUpdatesRequest {
+            ty,
+            since,
+            until,
+            basis,
+            lib_id,
+            lib_group,
+            max_records: _,
+        } = value;
+        let from = parse_yyyymmdd("since", &since)?;
+        if let Some(until) = &until
+            && parse_yyyymmdd("until", until)? < from
+        {
+            return Err("untilにはsince以降の日付を指定してください".to_string());
+        }
+        let since = since.trim().to_string();
+        let until = until.map(|until| until.trim().to_string());
+        let mut condition = Condition {
+            query: None,
+            crt_date_from: None,
+            crt_date_to: None,
+            reg_date_from: None,
+            reg_date_to: None,
+            lst_date_from: None,
+            lst_date_to: None,
+        };
+        match basis {
+            RecentBasis::Registered => {
+                condition.reg_date_from = Some(since);
+                condition.reg_date_to = until;
+            }
+            RecentBasis::Updated => {
+                condition.lst_date_from = Some(since);
+                condition.lst_date_to = until;
+            }
+        }
+        Ok(CrdSearchRequest {
+            ty,
+            condition,
+            lib_id,
+            lib_group,
+            lib_type: None,
+            results_get_position: None,
+            results_num: default_results_num(),
+        })
+    }
+}
+
+fn default_reference_type() -> ReqType {
+    ReqType::Reference
+}
+
+/// ISBNによる参考資料の検索条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct IsbnSearchRequest {
+    /// 参考資料のISBN。ISBN-10・ISBN-13のどちらでもよく、ハイフンの有無は問わない。
+    pub isbn: String,
+    /// 検索対象。referenceまたはmanualを指定する。デフォルトはreference。
+    #[serde(rename = "type", default = "default_reference_type")]
+    pub ty: ReqType,
+    /// 提供館コード
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_id: Option<String>,
+    /// 検索対象の図書館グループを指定する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_group: Option<LibGroup>,
+    /// 検索結果返却件数。デフォルトは100件。
+    #[serde(default = "default_results_num")]
+    #[schemars(range(min = 0, max = 100))]
+    pub results_num: i8,
+}
+
+impl TryFrom<IsbnSearchRequest> for CrdSearchRequest {
+    type Error = String;
+
+    /// ISBN-10・ISBN-13のどちらの形式で登録された参考資料にも一致する検索条件に変換する。
+    fn try_from(value: IsbnSearchRequest) -> Result<Self, Self::Error> {
+        let IsbnSearchRequest {
+            isbn,
+            ty,
+            lib_id,
+            lib_group,
+            results_num,
+        } = value;
+        if !matches!(ty, ReqType::Reference | ReqType::Manual) {
+            return Err(format!(
+                "ISBNで検索できるのはreferenceまたはmanualです: {}",
+                ty
+            ));
+        }
+        let isbn = Isbn::parse(&isbn).map_err(|e| format!("{}: {}", e, isbn))?;
+        Ok(CrdSearchRequest {
+            ty,
+            condition: Condition {
+                query: Some(isbn.cql_clause()),
+                crt_date_from: None,
+                crt_date_to: None,
+                reg_date_from: None,
+                reg_date_to: None,
+                lst_date_from: None,
+                lst_date_to: None,
+            },
+            lib_id,
+            lib_group,
+            lib_type: None,
+            results_get_position: None,
+            results_num,
+        })
+    }
+}
+
+fn default_find_libraries_num() -> i8 {
+    50
+}
+
+/// 参加館の検索条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct FindLibrariesRequest {
+    /// 都道府県名(例 北海道、東京都)。「都」「府」「県」は省略できる。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefecture: Option<String>,
+    /// 市区町村名(例 札幌市、千代田区)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    /// 館種
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_type: Option<LibType>,
+    /// 図書館名・特色・沿革などを対象とするキーワード。空白で区切った語をすべて含む館を検索する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyword: Option<String>,
+    /// 検索結果返却件数。デフォルトは50件。
+    #[serde(default = "default_find_libraries_num")]
+    #[schemars(range(min = 1, max = 100))]
+    pub results_num: i8,
+}
+
+impl TryFrom<FindLibrariesRequest> for CrdSearchRequest {
+    type Error = String;
+
+    /// 参加館プロファイルの `address`・`anywhere` の検索句に変換する。
+    fn try_from(value: FindLibrariesRequest) -> Result<Self, Self::Error> {
+        let FindLibrariesRequest {
+            prefecture,
+            city,
+            lib_type,
+            keyword,
+            results_num,
+        } = value;
+        let mut clauses = Vec::new();
+        if let Some(prefecture) = prefecture.filter(|p| !p.trim().is_empty()) {
+            let normalized = normalize_prefecture(&prefecture)
+                .ok_or_else(|| format!("都道府県名が正しくありません: {}", prefecture))?;
+            clauses.push(format!("address any {}", quote(normalized)));
+        }
+        if let Some(city) = city.filter(|c| !c.trim().is_empty()) {
+            clauses.push(format!("address any {}", quote(&city)));
+        }
+        if let Some(keyword) = keyword.filter(|k| !k.trim().is_empty()) {
+            clauses.push(format!("anywhere all {}", quote(&keyword)));
+        }
+        if clauses.is_empty() && lib_type.is_none() {
+            return Err(
+                "prefecture, city, lib_type, keyword のうち少なくとも1つは指定してください"
+                    .to_string(),
+            );
+        }
+        Ok(CrdSearchRequest {
+            ty: ReqType::Profile,
+            condition: Condition {
+                query: (!clauses.is_empty()).then(|| clauses.join(" and ")),
+                crt_date_from: None,
+                crt_date_to: None,
+                reg_date_from: None,
+                reg_date_to: None,
+                lst_date_from: None,
+                lst_date_to: None,
+            },
+            lib_id: None,
+            lib_group: None,
+            lib_type,
+            results_get_position: None,
+            results_num,
+        })
+    }
+}
+
+fn default_isil_max_records() -> u32 {
+    2000
+}
+
+/// ISILの検証・参加館の照会条件。isil・lib_id・lib_groupのいずれか1つを指定する。
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct IsilRequest {
+    /// 形式を検証し、参加館を照会するISIL(例 JP-1000001)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isil: Option<String>,
+    /// ISILを照会する参加館の図書館コード
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_id: Option<String>,
+    /// 参加館とISILを一覧する図書館グループ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_group: Option<LibGroup>,
+    /// lib_groupを指定した場合に取得する最大件数。デフォルトは2000件。
+    #[serde(default = "default_isil_max_records")]
+    #[schemars(range(min = 1, max = 10000))]
+    pub max_records: u32,
+}
+
+fn default_facets_max_records() -> u32 {
+    1000
+}
+
+fn default_facets_top() -> u32 {
+    20
+}
+
+/// 検索結果の集計条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct FacetsRequest {
+    /// 検索条件。results_get_positionから、max_records件までページングして取得する。
+    pub search: CrdSearchRequest,
+    /// 集計する最大レコード数。デフォルトは1000件。
+    #[serde(default = "default_facets_max_records")]
+    #[schemars(range(min = 1, max = 5000))]
+    pub max_records: u32,
+    /// 各項目で返す値の数(件数の多い順)。デフォルトは20。
+    #[serde(default = "default_facets_top")]
+    #[schemars(range(min = 1, max = 100))]
+    pub top: u32,
+}
+
+fn default_unresolved_max_records() -> u32 {
+    1000
+}
+
+fn default_unresolved_limit() -> u32 {
+    20
+}
+
+/// 未解決事例の並び順
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AgeOrder {
+    /// 事例作成日の古い順
+    #[default]
+    Oldest,
+    /// 事例作成日の新しい順
+    Newest,
+}
+
+/// 未解決のレファレンス事例の検索条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct UnresolvedRequest {
+    /// NDC分類コード(前方一致、例 21)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ndc: Option<String>,
+    /// キーワード。空白で区切った語をすべて含む事例を検索する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyword: Option<String>,
+    /// 提供館の所在地の都道府県名(例 北海道)。「都」「府」「県」は省略できる。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefecture: Option<String>,
+    /// 検索対象の図書館グループを指定する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_group: Option<LibGroup>,
+    /// 事例作成日FROM。YYYYMMDDで指定。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_from: Option<String>,
+    /// 事例作成日TO。YYYYMMDDで指定。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_to: Option<String>,
+    /// 並び順。デフォルトは古い順。
+    #[serde(default)]
+    pub order: AgeOrder,
+    /// 返却する件数。デフォルトは20件。
+    #[serde(default = "default_unresolved_limit")]
+    #[schemars(range(min = 1, max = 100))]
+    pub limit: u32,
+    /// 並べ替えのために取得する最大件数。デフォルトは1000件。
+    #[serde(default = "default_unresolved_max_records")]
+    #[schemars(range(min = 1, max = 5000))]
+    pub max_records: u32,
+}
+
+impl TryFrom<&UnresolvedRequest> for CrdSearchRequest {
+    type Error = String;
+
+    /// 事例作成日を検証し、`solution = 1` に、NDC・キーワードの検索句を加えた検索条件に変換する。
+    fn try_from(value: &UnresolvedRequest) -> Result<Self, Self::Error> {
+        let from = value
+            .created_from
+            .as_deref()
+            .map(|from| parse_yyyymmdd("created_from", from))
+            .transpose()?;
+        let to = value
+            .created_to
+            .as_deref()
+            .map(|to| parse_yyyymmdd("created_to", to))
+            .transpose()?;
+        if let (Some(from), Some(to)) = (from, to)
+            && to < from
+        {
+            return Err("created_toにはcreated_from以降の日付を指定してください".to_string());
+        }
+        let mut clauses = vec!["solution = 1".to_string()];
+        if let Some(ndc) = value
+            .ndc
+            .as_deref()
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+        {
+            clauses.push(format!("ndc = {}", quote(ndc)));
+        }
+        if let Some(keyword) = value.keyword.as_deref().filter(|k| !k.trim().is_empty()) {
+            clauses.push(format!("anywhere all {}", quote(keyword)));
+        }
+        Ok(CrdSearchRequest {
+            ty: ReqType::Reference,
+            condition: Condition {
+                query: Some(clauses.join(" and ")),
+                crt_date_from: value.created_from.as_deref().map(|d| d.trim().to_string()),
+                crt_date_to: value.created_to.as_deref().map(|d| d.trim().to_string()),
+                reg_date_from: None,
+                reg_date_to: None,
+                lst_date_from: None,
+                lst_date_to: None,
+            },
+            lib_id: None,
+            lib_group: value.lib_group,
+            lib_type: None,
+            results_get_position: None,
+            results_num: default_results_num(),
+        })
+    }
+}
+
+/// NDCの区分ごとのヒット件数の取得条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct BrowseNdcRequest {
+    /// 下位区分を一覧する分類記号。類(1桁)または綱(2桁)を指定する。指定がない場合は類(0〜9)を一覧する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// 見出しに使うNDCの版。デフォルトは10版。
+    #[serde(default)]
+    pub edition: NdcEdition,
+    /// 検索対象。reference・manual・collectionのいずれか。デフォルトはreference。
+    #[serde(rename = "type", default = "default_reference_type")]
+    pub ty: ReqType,
+    /// 提供館コード
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_id: Option<String>,
+    /// 検索対象の図書館グループを指定する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lib_group: Option<LibGroup>,
+}
+
+fn default_export_max_records() -> u32 {
+    1000
+}
+
+/// 検索結果の出力条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ExportRequest {
+    /// 検索条件。results_get_positionから、max_records件までページングして取得する。
+    pub search: CrdSearchRequest,
+    /// 出力形式
+    pub format: ExportFormat,
+    /// 取得する最大件数。デフォルトは1000件。
+    #[serde(default = "default_export_max_records")]
+    #[schemars(range(min = 1, max = 10000))]
+    pub max_records: u32,
+}
+
+fn default_bibliography_max_records() -> u32 {
+    100
+}
+
+/// 参考資料の書誌の出力条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct BibliographyRequest {
+    /// 検索条件。特定の事例の参考資料を出力する場合は `sys-id = 登録番号` をqueryに指定する。
+    pub search: CrdSearchRequest,
+    /// 出力形式。RISとCSL-JSONはZoteroなどの文献管理ソフトに取り込める。
+    pub format: BibliographyFormat,
+    /// 参考資料を集める最大レコード数。デフォルトは100件。
+    #[serde(default = "default_bibliography_max_records")]
+    #[schemars(range(min = 1, max = 2000))]
+    pub max_records: u32,
+}
+
+fn default_local_limit() -> u32 {
+    20
+}
+
+/// ローカルミラーの全文検索条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct LocalSearchRequest {
+    /// 検索語。空白で区切った複数の語を指定できる。CQLではなく自然な語句で指定する。
+    pub query: String,
+    /// 検索対象。reference, manual, collectionのいずれか。指定がない場合はすべてを対象とする。
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub ty: Option<ReqType>,
+    /// 複数の検索語の結合方法。デフォルトはすべての語を含むもの。
+    #[serde(default)]
+    pub mode: MatchMode,
+    /// 検索結果返却件数。デフォルトは20件。
+    #[serde(default = "default_local_limit")]
+    #[schemars(range(min = 1, max = 100))]
+    pub limit: u32,
+}
+
+/// 複数の検索語の結合方法
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// すべての語を含む
+    #[default]
+    All,
+    /// いずれかの語を含む
+    Any,
+}
+
+#[cfg(feature = "embeddings")]
+fn default_similar_limit() -> u32 {
+    10
+}
+
+fn default_suggest_limit() -> u32 {
+    10
+}
+
+/// キーワードの候補の検索条件
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SuggestKeywordsRequest {
+    /// キーワードの一部(例 郷土)
+    pub query: String,
+    /// 返却するキーワードの件数。デフォルトは10件。
+    #[serde(default = "default_suggest_limit")]
+    #[schemars(range(min = 1, max = 50))]
+    pub limit: u32,
+}
+
+/// キーワードの候補を示すプロンプトの引数
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SuggestKeywordsPrompt {
+    /// 調べたい事柄を表す語の一部(例 郷土)
+    pub query: String,
+}
+
+/// 検索条件を組み立てるプロンプトの引数
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SearchCasesPrompt {
+    /// 調べたい事柄(例 郷土料理)
+    pub topic: String,
+    /// 検索対象(reference, manual, collection, profile, all)。デフォルトはreference。
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub ty: Option<String>,
+    /// 調べたい事柄を検索するクエリー対象項目(例 question)。デフォルトはanywhere。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    /// NDC分類コード(前方一致、例 383)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ndc: Option<String>,
+    /// 提供館の図書館コード
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lib_id: Option<String>,
+    /// 図書館グループ(all, ndl, public, academic, special, school, archives)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lib_group: Option<String>,
+    /// 提供館の所在地の都道府県名(例 北海道)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefecture: Option<String>,
+}
+
+/// 類似事例の検索条件
+#[cfg(feature = "embeddings")]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SimilarCasesRequest {
+    /// 類似する事例を探す文章(質問文など)。sys_idとどちらか一方を指定する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// 類似する事例を探すレファレンス事例の登録番号。textとどちらか一方を指定する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sys_id: Option<String>,
+    /// 検索結果返却件数。デフォルトは10件。
+    #[serde(default = "default_similar_limit")]
+    #[schemars(range(min = 1, max = 50))]
+    pub limit: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AgeOrder, CrdSearchRequest, FindLibrariesRequest, LibGroup, LibType, RecentBasis,
+        RecentUpdatesRequest, ReqType, SearchRequest, UnresolvedRequest,
+    };
+
+    #[test]
+    fn test_lib_type() {
+        assert_eq!("21".parse::<LibType>(), Ok(LibType::PublicPrefectural));
+        assert_eq!(
+            "公共図書館（都道府県立）".parse::<LibType>(),
+            Ok(LibType::PublicPrefectural)
+        );
+        assert!("25".parse::<LibType>().is_err());
+        assert_eq!(LibType::AcademicTechnical.group(), Some(LibGroup::Academic));
+        assert_eq!(LibType::from_code("21"), LibType::PublicPrefectural);
+        let unknown = LibType::from_code("25");
+        assert_eq!(unknown, LibType::Unknown("25".to_string()));
+        assert_eq!(
+            (unknown.code(), unknown.label(), unknown.group()),
+            ("25", None, None)
+        );
+        assert_eq!(
+            LibGroup::Public
+                .lib_types()
+                .iter()
+                .map(LibType::code)
+                .collect::<Vec<_>>(),
+            vec!["21", "22", "23", "24"]
+        );
+        assert_eq!(LibGroup::All.lib_types().len(), 21);
+
+        let request = |ty: &str, lib_group: Option<&str>| {
+            serde_json::from_value::<CrdSearchRequest>(serde_json::json!({
+                "type": ty,
+                "query": "anywhere any 郷土",
+                "lib_group": lib_group,
+                "lib_type": "21",
+            }))
+            .unwrap()
+        };
+        assert!(request("profile", Some("public")).validate().is_ok());
+        assert!(request("reference", None).validate().is_err());
+        assert!(request("profile", Some("academic")).validate().is_err());
+    }
+
+    #[test]
+    fn test_recent_updates_request() {
+        let request = |since: &str, until: Option<&str>| RecentUpdatesRequest {
+            ty: ReqType::Reference,
+            since: since.to_string(),
+            until: until.map(str::to_string),
+            basis: RecentBasis::Updated,
+            lib_id: None,
+            lib_group: None,
+            max_records: 500,
+        };
+        let search = CrdSearchRequest::try_from(request(" 20240401", Some("20240410"))).unwrap();
+        assert_eq!(search.condition.lst_date_from.as_deref(), Some("20240401"));
+        assert_eq!(search.condition.lst_date_to.as_deref(), Some("20240410"));
+        assert!(CrdSearchRequest::try_from(request("2024-04-01", None)).is_err());
+        assert!(CrdSearchRequest::try_from(request("20240231", None)).is_err());
+        assert!(CrdSearchRequest::try_from(request("20240401", Some("先週"))).is_err());
+        assert!(CrdSearchRequest::try_from(request("20240410", Some("20240401"))).is_err());
+    }
+
+    #[test]
+    fn test_unresolved_request() {
+        let request = |from: Option<&str>, to: Option<&str>| UnresolvedRequest {
+            ndc: Some("21".to_string()),
+            keyword: None,
+            prefecture: None,
+            lib_group: None,
+            created_from: from.map(str::to_string),
+            created_to: to.map(str::to_string),
+            order: AgeOrder::default(),
+            limit: 20,
+            max_records: 1000,
+        };
+        let search = CrdSearchRequest::try_from(&request(Some("20200101 "), None)).unwrap();
+        assert_eq!(
+            search.condition.query.as_deref(),
+            Some("solution = 1 and ndc = \"21\"")
+        );
+        assert_eq!(search.condition.crt_date_from.as_deref(), Some("20200101"));
+        assert!(CrdSearchRequest::try_from(&request(Some("2020"), None)).is_err());
+        assert!(CrdSearchRequest::try_from(&request(None, Some("20201301"))).is_err());
+        assert!(CrdSearchRequest::try_from(&request(Some("20200201"), Some("20200101"))).is_err());
+    }
+
+    #[test]
+    fn test_find_libraries_request() {
+        let request = |prefecture: Option<&str>, keyword: Option<&str>| FindLibrariesRequest {
+            prefecture: prefecture.map(str::to_string),
+            city: Some("札幌市".to_string()),
+            lib_type: None,
+            keyword: keyword.map(str::to_string),
+            results_num: 50,
+        };
+        let search =
+            CrdSearchRequest::try_from(request(Some("北海道"), Some("郷土 資料"))).unwrap();
+        assert_eq!(
+            search.condition.query.unwrap(),
+            "address any \"北海道\" and address any \"札幌市\" and anywhere all \"郷土 資料\""
+        );
+        assert!(CrdSearchRequest::try_from(request(Some("札幌"), None)).is_err());
+    }
+
+    #[test]
+    fn test_search_request() {
+        let request: SearchRequest = serde_json::from_value(serde_json::json!({
+            "type": "reference",
+            "query": "question any 北海道",
+            "include_library": true,
+        }))
+        .unwrap();
+        assert!(request.include_library);
+        let search = request.search.unwrap();
+        assert_eq!(search.results_num, 100);
+        assert_eq!(
+            search.condition.query.as_deref(),
+            Some("question any 北海道")
+        );
+
+        let request: SearchRequest = serde_json::from_value(serde_json::json!({
+            "type": "reference",
+            "query": "solution = 0",
+            "clauses": [
+                { "index": "question", "relation": "any", "terms": ["本", "音楽"] },
+                { "terms": ["北海道"] },
+            ],
+            "expand": true,
+            "relax": true,
+        }))
+        .unwrap();
+        assert!(request.expand && request.relax);
+        assert_eq!(
+            request.search.unwrap().condition.query.as_deref(),
+            Some(r#"solution = "0" and question any "本 音楽" and anywhere all "北海道""#)
+        );
+
+        let request: SearchRequest =
+            serde_json::from_value(serde_json::json!({ "cursor": "abc" })).unwrap();
+        assert!(request.search.is_none());
+        assert!(
+            serde_json::from_value::<SearchRequest>(serde_json::json!({ "include_library": true }))
+                .is_err()
+        );
+    }
+}
@@ -1,17 +1,273 @@
 use crate::crd::{
-    Bibl, CrdCollectionResult, CrdManualResult, CrdProfileResult, CrdReferenceResult, CrdResult,
-    CrdResultSet, CrdSystem, CrdSystemWithoutSysId, NdcClass,
+    Bibl, Completion, Continuation, CrdCollectionResult, CrdManualResult, CrdProfileResult,
+    CrdReferenceResult, CrdResult, CrdResultSet, CrdSystem, CrdSystemWithoutSysId, MalformedResult,
+    NdcClass, Solution,
 };
+use crate::date::{CrdDate, CrdDateTime};
+use crate::isbn::Isbn;
+use crate::ndc::{self, NdcEdition};
+use crate::query::Relaxation;
+use crate::req::{AgeOrder, LibType, ReqType, SortKey, SortOrder};
+use chrono::{Datelike, NaiveDate};
 use rmcp::ErrorData;
 use schemars::JsonSchema;
-use serde::Serialize;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Serialize, JsonSchema, Debug, Clone)]
 pub struct CrdSearchResponse {
     pub hit_count: i32,
     pub cursor_position: i32,
     pub results_returned: i32,
+    /// 続きのレコードがある場合はtrue
+    pub has_more: bool,
+    /// 続きを取得するためのカーソル。searchのcursorに指定する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
     pub results: Vec<CrdSearchResult>,
+    /// 読み込めなかったレコードの警告。該当するレコードは `Malformed` として `results` に含める。
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// 並べ替えを行った場合の並べ替えの条件
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortInfo>,
+    /// type = "all" の場合の種類ごとの検索結果。この場合、resultsは空となる。
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<SearchSection>,
+    /// expand = trueの場合の、表記ゆれ・同義語で展開した検索式
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expanded_query: Option<String>,
+    /// relax = trueでヒットしなかった場合の、検索式の緩和の経過
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relaxation: Option<RelaxationReport>,
+}
+
+/// ヒットしなかった検索式を緩和した経過
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct RelaxationReport {
+    /// 元の検索式
+    pub original_query: String,
+    /// 緩和の各段階
+    pub steps: Vec<RelaxationStep>,
+    /// ヒットした検索式。いずれの段階でもヒットしなかった場合は省略する。
+    /// ヒットした場合、resultsとnext_cursorはこの検索式の検索結果となる。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_query: Option<String>,
+}
+
+/// 検索式の緩和の段階
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct RelaxationStep {
+    pub relaxation: Relaxation,
+    /// 緩和した検索式
+    pub query: String,
+    /// ヒット件数。検索に失敗した場合は省略する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hit_count: Option<i32>,
+    /// 検索に失敗した場合のエラー
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// type = "all" の検索結果の種類ごとの区分
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct SearchSection {
+    /// レコードの種類
+    #[serde(rename = "type")]
+    pub ty: ReqType,
+    /// 種類ごとの検索結果。続きはnext_cursorで取得する。
+    #[serde(flatten)]
+    pub response: Option<CrdSearchResponse>,
+    /// この種類の検索に失敗した場合のエラー
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 並べ替えの条件と対象
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct SortInfo {
+    pub sort_by: SortKey,
+    pub order: SortOrder,
+    /// 並べ替えの対象としたレコード数
+    pub records_sorted: usize,
+    /// 取得上限に達し、一部のレコードを並べ替えの対象にしていない場合はtrue
+    pub truncated: bool,
+}
+
+/// 複数の検索条件による検索結果
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct MultiSearchResponse {
+    /// 検索条件のlabelごとの検索結果
+    pub searches: BTreeMap<String, LabeledSearchResponse>,
+    /// merge = trueの場合の、全ての検索結果をsys_idで重複を除いてまとめたレコード
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merged: Option<Vec<MergedResult>>,
+}
+
+/// labelごとの検索結果
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct LabeledSearchResponse {
+    /// 検索結果。続きはnext_cursorをsearchのcursorに指定して取得する。
+    #[serde(flatten)]
+    pub response: Option<CrdSearchResponse>,
+    /// この検索条件の検索に失敗した場合のエラー
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 重複を除いてまとめたレコード
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct MergedResult {
+    /// このレコードを含む検索条件のlabel
+    pub labels: Vec<String>,
+    pub result: CrdSearchResult,
+}
+
+impl MultiSearchResponse {
+    /// labelごとの検索結果をまとめる。
+    ///
+    /// `merge` の場合は検索条件の順にレコードを並べ、同じsys_idのレコードは最初のものにlabelを加える。
+    /// sys_idのないレコードはまとめずにそれぞれ含める。
+    pub fn new(searches: Vec<(String, Result<CrdSearchResponse, String>)>, merge: bool) -> Self {
+        let merged = merge.then(|| {
+            let mut merged: Vec<MergedResult> = Vec::new();
+            let mut index: HashMap<String, usize> = HashMap::new();
+            for (label, response) in &searches {
+                let Ok(response) = response else {
+                    continue;
+                };
+                for result in &response.results {
+                    let sys_id = result.sys_id().filter(|id| !id.is_empty());
+                    match sys_id.and_then(|id| index.get(id)) {
+                        Some(&i) => {
+                            let labels = &mut merged[i].labels;
+                            if !labels.contains(label) {
+                                labels.push(label.clone());
+                            }
+                        }
+                        None => {
+                            if let Some(id) = sys_id {
+                                index.insert(id.to_string(), merged.len());
+                            }
+                            merged.push(MergedResult {
+                                labels: vec![label.clone()],
+                                result: result.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+            merged
+        });
+        let searches = searches
+            .into_iter()
+            .map(|(label, response)| {
+                let response = match response {
+                    Ok(response) => LabeledSearchResponse {
+                        response: Some(response),
+                        error: None,
+                    },
+                    Err(error) => LabeledSearchResponse {
+                        response: None,
+                        error: Some(error),
+                    },
+                };
+                (label, response)
+            })
+            .collect();
+        MultiSearchResponse { searches, merged }
+    }
+}
+
+impl CrdSearchResponse {
+    /// 種類ごとの区分をまとめる。ヒット件数・返却件数は各区分の合計とする。
+    pub fn from_sections(sections: Vec<SearchSection>) -> Self {
+        let responses = sections.iter().filter_map(|s| s.response.as_ref());
+        let hit_count = responses.clone().map(|r| r.hit_count).sum();
+        let results_returned = responses.map(|r| r.results_returned).sum();
+        CrdSearchResponse {
+            hit_count,
+            cursor_position: 0,
+            results_returned,
+            has_more: false,
+            next_cursor: None,
+            results: Vec::new(),
+            warnings: Vec::new(),
+            sort: None,
+            sections,
+            expanded_query: None,
+            relaxation: None,
+        }
+    }
+
+    /// 取得済みのレコードを並べ替え、`position` から `num` 件を返す。
+    ///
+    /// 値が同じレコードはCRD APIの返却順を保ち、値のないレコードは並び順によらず末尾とする。
+    /// 同じ検索条件であれば同じ順序となるため、`position` を進めて続きを取得できる。
+    pub fn sorted(
+        mut self,
+        sort_by: SortKey,
+        order: SortOrder,
+        position: usize,
+        num: usize,
+    ) -> Self {
+        let records_sorted = self.results.len();
+        match sort_by {
+            SortKey::CreatedAt => sort_by_value(&mut self.results, order, |r| {
+                r.created_at().and_then(|date| date.value)
+            }),
+            SortKey::RegDate => sort_by_value(&mut self.results, order, |r| {
+                r.reg_date().and_then(|date| date.value)
+            }),
+            SortKey::LstDate => sort_by_value(&mut self.results, order, |r| {
+                r.lst_date().and_then(|date| date.value)
+            }),
+            SortKey::LibName => sort_by_value(&mut self.results, order, |r| {
+                Some(r.lib_name().to_string()).filter(|name| !name.is_empty())
+            }),
+            SortKey::Relevance => {}
+        }
+        let results = self
+            .results
+            .into_iter()
+            .skip(position)
+            .take(num)
+            .collect::<Vec<_>>();
+        CrdSearchResponse {
+            hit_count: self.hit_count,
+            cursor_position: position as i32,
+            results_returned: results.len() as i32,
+            has_more: position + results.len() < records_sorted,
+            next_cursor: None,
+            results,
+            warnings: self.warnings,
+            sort: Some(SortInfo {
+                sort_by,
+                order,
+                records_sorted,
+                truncated: (records_sorted as i32) < self.hit_count,
+            }),
+            sections: Vec::new(),
+            expanded_query: None,
+            relaxation: None,
+        }
+    }
+}
+
+fn sort_by_value<T: Ord>(
+    results: &mut [CrdSearchResult],
+    order: SortOrder,
+    key: impl Fn(&CrdSearchResult) -> Option<T>,
+) {
+    results.sort_by(|a, b| match (key(a), key(b)) {
+        (Some(a), Some(b)) => match order {
+            SortOrder::Asc => a.cmp(&b),
+            SortOrder::Desc => b.cmp(&a),
+        },
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
 }
 
 #[derive(Serialize, JsonSchema, Debug, Clone)]
@@ -21,13 +277,15 @@ pub enum CrdSearchResult {
         question: String,
         registration_id: String,
         answer: String,
-        created_at: String,
-        is_solution: bool,
+        /// 作成日
+        created_at: CrdDate,
+        /// 解決/未解決
+        solution: Solution,
         #[serde(skip_serializing_if = "Option::is_none")]
         keywords: Option<Vec<String>>,
         /// 分類
         #[serde(skip_serializing_if = "Option::is_none")]
-        classes: Option<Vec<NdcClass>>,
+        classes: Option<Vec<ClassResult>>,
         /// 調査種別
         ///
         /// 「文献紹介」「事実調査」「書誌的事項調査」「所蔵調査」「所蔵機関調査」「利用案内」「その他」または任意の文字列
@@ -40,7 +298,7 @@ pub enum CrdSearchResult {
         content_type: Option<String>,
         /// 参考資料
         #[serde(skip_serializing_if = "Option::is_none")]
-        bibls: Option<Vec<Bibl>>,
+        bibls: Option<Vec<BiblResult>>,
         /// 回答プロセス
         #[serde(skip_serializing_if = "Option::is_none")]
         answer_process: Option<String>,
@@ -59,6 +317,9 @@ pub enum CrdSearchResult {
         /// 寄与者
         #[serde(skip_serializing_if = "Option::is_none")]
         contributors: Option<Vec<String>>,
+        /// 提供館の連絡先。include_libraryを指定した場合のみ。
+        #[serde(skip_serializing_if = "Option::is_none")]
+        library: Option<LibraryEntry>,
         /// その他の項目(システム管理項目)
         system: CrdSystem,
     },
@@ -67,19 +328,24 @@ pub enum CrdSearchResult {
         theme: String,
         registration_id: String,
         guide: String,
-        created_at: String,
-        is_completed: bool,
+        /// 作成日
+        created_at: CrdDate,
+        /// 完成/未完成
+        completion: Completion,
         #[serde(skip_serializing_if = "Option::is_none")]
         keywords: Option<Vec<String>>,
         /// 分類
         #[serde(skip_serializing_if = "Option::is_none")]
-        classes: Option<Vec<NdcClass>>,
+        classes: Option<Vec<ClassResult>>,
         /// 参考資料
         #[serde(skip_serializing_if = "Option::is_none")]
-        bibls: Option<Vec<Bibl>>,
+        bibls: Option<Vec<BiblResult>>,
         /// 備考
         #[serde(skip_serializing_if = "Option::is_none")]
         note: Option<String>,
+        /// 提供館の連絡先。include_libraryを指定した場合のみ。
+        #[serde(skip_serializing_if = "Option::is_none")]
+        library: Option<LibraryEntry>,
         /// その他の項目(システム管理項目)
         system: CrdSystem,
     },
@@ -103,23 +369,28 @@ pub enum CrdSearchResult {
         literature: Option<String>,
         /// 所蔵点数
         number: Option<String>,
-        is_continued: bool,
+        /// 継続/非継続
+        continuation: Continuation,
         #[serde(skip_serializing_if = "Option::is_none")]
         keywords: Option<Vec<String>>,
         /// 分類
         #[serde(skip_serializing_if = "Option::is_none")]
-        classes: Option<Vec<NdcClass>>,
+        classes: Option<Vec<ClassResult>>,
         /// 備考
         #[serde(skip_serializing_if = "Option::is_none")]
         note: Option<String>,
+        /// 提供館の連絡先。include_libraryを指定した場合のみ。
+        #[serde(skip_serializing_if = "Option::is_none")]
+        library: Option<LibraryEntry>,
         /// その他の項目(システム管理項目)
         system: CrdSystem,
     },
     Profile {
         url: String,
-        /// 図書館名(館種コード)
-        // todo: enum?
-        library_type: String,
+        /// 館種
+        #[serde(serialize_with = "serialize_lib_type")]
+        #[schemars(with = "LibTypeValue")]
+        library_type: LibType,
         library_name: String,
         library_name_kana: String,
         library_name_abbr: String,
@@ -161,21 +432,77 @@ pub enum CrdSearchResult {
         /// その他の項目(システム管理項目)
         system: CrdSystemWithoutSysId,
     },
+    /// 読み込めなかったレコード。読み取れた範囲の項目と、エラー・元のXMLを含む。
+    Malformed {
+        /// レコードの種類。判定できない場合は `all`。
+        #[serde(rename = "type")]
+        ty: ReqType,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sys_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lib_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lib_name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reg_date: Option<CrdDateTime>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lst_date: Option<CrdDateTime>,
+        /// 読み込めなかった理由
+        error: String,
+        /// `<result>` 要素のXML
+        raw_xml: String,
+    },
 }
 
 impl From<CrdResultSet> for Result<CrdSearchResponse, ErrorData> {
     fn from(value: CrdResultSet) -> Self {
         if value.results_cd == 0 {
+            let mut warnings = Vec::new();
+            let results = value
+                .result
+                .into_iter()
+                .enumerate()
+                .map(|(i, result)| match result {
+                    Ok(item) => {
+                        let item = CrdSearchResult::from(item);
+                        for (field, raw) in item.unknown_flags() {
+                            warnings.push(format!(
+                                "{}件目のレコード(登録番号: {})の{}の値を判定できませんでした: {}",
+                                value.results_get_position + i as i32 + 1,
+                                item.sys_id().unwrap_or("不明"),
+                                field,
+                                raw
+                            ));
+                        }
+                        item
+                    }
+                    Err(malformed) => {
+                        warnings.push(format!(
+                            "{}件目のレコード(登録番号: {})を読み込めませんでした: {}",
+                            value.results_get_position + i as i32 + 1,
+                            malformed.sys_id.as_deref().unwrap_or("不明"),
+                            malformed.error
+                        ));
+                        (*malformed).into()
+                    }
+                })
+                .collect();
+            let hit_count = value.hit_num.unwrap();
             Ok(CrdSearchResponse {
-                hit_count: value.hit_num.unwrap(),
+                hit_count,
                 cursor_position: value.results_get_position,
                 results_returned: value.results_num,
-                results: value
-                    .result
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(|x| x.item.into())
-                    .collect(),
+                has_more: value.results_num > 0
+                    && value.results_get_position + value.results_num < hit_count,
+                next_cursor: None,
+                results,
+                warnings,
+                sort: None,
+                sections: Vec::new(),
+                expanded_query: None,
+                relaxation: None,
             })
         } else {
             let errors = value
@@ -196,6 +523,113 @@ impl From<CrdResultSet> for Result<CrdSearchResponse, ErrorData> {
         }
     }
 }
+/// 見出しを付けたNDC分類
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct ClassResult {
+    /// 分類の種類(「NDC」のみ)
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// typeで指定された分類のバージョン(例 9(9版を示す))
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// 分類の番号
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    /// 類・綱・要目の見出し(例 `歴史 > 日本史 > 北海道地方`)。版が8〜10版以外の場合は付けない。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// 見出しが9版の表からの近似(8版・10版)の場合はtrue
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub label_approximate: bool,
+}
+
+impl From<NdcClass> for ClassResult {
+    fn from(value: NdcClass) -> Self {
+        let edition = NdcEdition::from_version(value.version.as_deref());
+        let label = edition
+            .zip(value.value.as_deref())
+            .and_then(|(edition, code)| ndc::describe(edition, code));
+        ClassResult {
+            ty: value.ty,
+            version: value.version,
+            value: value.value,
+            label_approximate: label.is_some() && edition.is_some_and(NdcEdition::is_approximate),
+            label,
+        }
+    }
+}
+
+/// `NDC9:123.4` の形式で表示する。
+impl Display for ClassResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}:{}",
+            self.ty,
+            self.version.as_deref().unwrap_or_default(),
+            self.value.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// ISBNを検証した参考資料
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct BiblResult {
+    /// 書誌的事項(参考資料)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+    /// ISBN（参考資料）。CRDに登録された文字列のまま。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isbn: Option<String>,
+    /// ハイフンなしのISBN-13に正規化したISBN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isbn13: Option<String>,
+    /// ISBNが不正な場合の理由
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isbn_error: Option<String>,
+    /// 備考(参考資料)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl From<Bibl> for BiblResult {
+    fn from(value: Bibl) -> Self {
+        let parsed = value
+            .isbn
+            .as_deref()
+            .filter(|isbn| !isbn.trim().is_empty())
+            .map(Isbn::parse);
+        let (isbn13, isbn_error) = match parsed {
+            Some(Ok(isbn)) => (Some(isbn.isbn13().to_string()), None),
+            Some(Err(e)) => (None, Some(e.to_string())),
+            None => (None, None),
+        };
+        BiblResult {
+            desc: value.desc,
+            isbn: value.isbn,
+            isbn13,
+            isbn_error,
+            note: value.note,
+        }
+    }
+}
+
+/// `書誌的事項 ISBN:xxx (備考)` の形式で表示する。ISBNは正規化できた場合はISBN-13で表示する。
+impl Display for BiblResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts = [
+            self.desc.clone(),
+            self.isbn13
+                .as_ref()
+                .or(self.isbn.as_ref())
+                .map(|isbn| format!("ISBN:{}", isbn)),
+            self.note.as_ref().map(|note| format!("({})", note)),
+        ];
+        let parts = parts.into_iter().flatten().collect::<Vec<_>>();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
 impl From<CrdResult> for CrdSearchResult {
     fn from(value: CrdResult) -> Self {
         match value {
@@ -224,18 +658,19 @@ impl From<CrdResult> for CrdSearchResult {
                 registration_id: reg_id,
                 answer,
                 created_at: crt_date,
-                is_solution: solution == Some(0),
+                solution,
                 keywords,
-                classes,
+                classes: classes.map(|c| c.into_iter().map(ClassResult::from).collect()),
                 survey_type: res_type,
                 content_type: con_type,
-                bibls,
+                bibls: bibls.map(|b| b.into_iter().map(BiblResult::from).collect()),
                 answer_process: ans_proc,
                 referrals,
                 pre_survey: pre_res,
                 note,
                 questioner_type: ptn_type,
                 contributors: contri,
+                library: None,
                 system,
             },
             CrdResult::Manual(CrdManualResult {
@@ -256,11 +691,12 @@ impl From<CrdResult> for CrdSearchResult {
                 registration_id: reg_id,
                 guide,
                 created_at: crt_date,
-                is_completed: completion == Some(0),
+                completion,
                 keywords,
-                classes,
-                bibls,
+                classes: classes.map(|c| c.into_iter().map(ClassResult::from).collect()),
+                bibls: bibls.map(|b| b.into_iter().map(BiblResult::from).collect()),
                 note,
+                library: None,
                 system,
             },
             CrdResult::Collection(CrdCollectionResult {
@@ -290,10 +726,11 @@ impl From<CrdResult> for CrdSearchResult {
                 catalog,
                 literature,
                 number,
-                is_continued: r#continue == Some("0".to_string()),
+                continuation: r#continue,
                 keywords,
-                classes,
+                classes: classes.map(|c| c.into_iter().map(ClassResult::from).collect()),
                 note,
+                library: None,
                 system,
             },
             CrdResult::Profile(CrdProfileResult {
@@ -354,3 +791,960 @@ impl From<CrdResult> for CrdSearchResult {
         }
     }
 }
+
+impl From<MalformedResult> for CrdSearchResult {
+    fn from(value: MalformedResult) -> Self {
+        let MalformedResult {
+            ty,
+            url,
+            sys_id,
+            lib_id,
+            lib_name,
+            reg_date,
+            lst_date,
+            error,
+            raw_xml,
+        } = value;
+        CrdSearchResult::Malformed {
+            ty: ty.and_then(|ty| ty.parse().ok()).unwrap_or(ReqType::All),
+            url,
+            sys_id,
+            lib_id,
+            lib_name,
+            reg_date: reg_date.map(CrdDateTime::new),
+            lst_date: lst_date.map(CrdDateTime::new),
+            error,
+            raw_xml,
+        }
+    }
+}
+
+impl CrdSearchResult {
+    /// レコードの種類
+    pub fn kind(&self) -> ReqType {
+        match self {
+            CrdSearchResult::Reference { .. } => ReqType::Reference,
+            CrdSearchResult::Manual { .. } => ReqType::Manual,
+            CrdSearchResult::Collection { .. } => ReqType::Collection,
+            CrdSearchResult::Profile { .. } => ReqType::Profile,
+            CrdSearchResult::Malformed { ty, .. } => *ty,
+        }
+    }
+
+    /// 見出し(質問・調査テーマ・コレクション名・図書館名)
+    pub fn title(&self) -> &str {
+        match self {
+            CrdSearchResult::Reference { question, .. } => question,
+            CrdSearchResult::Manual { theme, .. } => theme,
+            CrdSearchResult::Collection { name, .. } => name,
+            CrdSearchResult::Profile { library_name, .. } => library_name,
+            CrdSearchResult::Malformed { .. } => "",
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            CrdSearchResult::Reference { url, .. }
+            | CrdSearchResult::Manual { url, .. }
+            | CrdSearchResult::Collection { url, .. }
+            | CrdSearchResult::Profile { url, .. } => url,
+            CrdSearchResult::Malformed { url, .. } => url.as_deref().unwrap_or_default(),
+        }
+    }
+
+    /// 登録番号。参加館プロファイルには存在しない。
+    pub fn sys_id(&self) -> Option<&str> {
+        match self {
+            CrdSearchResult::Reference { system, .. }
+            | CrdSearchResult::Manual { system, .. }
+            | CrdSearchResult::Collection { system, .. } => Some(&system.sys_id),
+            CrdSearchResult::Profile { .. } => None,
+            CrdSearchResult::Malformed { sys_id, .. } => sys_id.as_deref(),
+        }
+    }
+
+    /// 提供館コード
+    pub fn lib_id(&self) -> &str {
+        match self {
+            CrdSearchResult::Reference { system, .. }
+            | CrdSearchResult::Manual { system, .. }
+            | CrdSearchResult::Collection { system, .. } => &system.lib_id,
+            CrdSearchResult::Profile { system, .. } => &system.lib_id,
+            CrdSearchResult::Malformed { lib_id, .. } => lib_id.as_deref().unwrap_or_default(),
+        }
+    }
+
+    /// 提供館名
+    pub fn lib_name(&self) -> &str {
+        match self {
+            CrdSearchResult::Reference { system, .. }
+            | CrdSearchResult::Manual { system, .. }
+            | CrdSearchResult::Collection { system, .. } => &system.lib_name,
+            CrdSearchResult::Profile { system, .. } => &system.lib_name,
+            CrdSearchResult::Malformed { lib_name, .. } => lib_name.as_deref().unwrap_or_default(),
+        }
+    }
+
+    /// 登録日時
+    pub fn reg_date(&self) -> Option<&CrdDateTime> {
+        match self {
+            CrdSearchResult::Reference { system, .. }
+            | CrdSearchResult::Manual { system, .. }
+            | CrdSearchResult::Collection { system, .. } => Some(&system.reg_date),
+            CrdSearchResult::Profile { system, .. } => Some(&system.reg_date),
+            CrdSearchResult::Malformed { reg_date, .. } => reg_date.as_ref(),
+        }
+    }
+
+    /// 分類
+    pub fn classes(&self) -> &[ClassResult] {
+        match self {
+            CrdSearchResult::Reference { classes, .. }
+            | CrdSearchResult::Manual { classes, .. }
+            | CrdSearchResult::Collection { classes, .. } => classes.as_deref().unwrap_or_default(),
+            CrdSearchResult::Profile { .. } | CrdSearchResult::Malformed { .. } => &[],
+        }
+    }
+
+    /// 参考資料
+    pub fn bibls(&self) -> &[BiblResult] {
+        match self {
+            CrdSearchResult::Reference { bibls, .. } | CrdSearchResult::Manual { bibls, .. } => {
+                bibls.as_deref().unwrap_or_default()
+            }
+            CrdSearchResult::Collection { .. }
+            | CrdSearchResult::Profile { .. }
+            | CrdSearchResult::Malformed { .. } => &[],
+        }
+    }
+
+    /// 0/1で表される区分のうち、判定できなかった項目と値
+    pub fn unknown_flags(&self) -> Vec<(&'static str, &str)> {
+        let flag = match self {
+            CrdSearchResult::Reference { solution, .. } => ("solution", solution.unknown_value()),
+            CrdSearchResult::Manual { completion, .. } => {
+                ("completion", completion.unknown_value())
+            }
+            CrdSearchResult::Collection { continuation, .. } => {
+                ("continue", continuation.unknown_value())
+            }
+            CrdSearchResult::Profile { .. } | CrdSearchResult::Malformed { .. } => {
+                return Vec::new();
+            }
+        };
+        match flag {
+            (field, Some(value)) => vec![(field, value)],
+            (_, None) => Vec::new(),
+        }
+    }
+
+    /// 提供館の連絡先を付与する。参加館プロファイルには付与しない。
+    pub fn set_library(&mut self, entry: Option<LibraryEntry>) {
+        match self {
+            CrdSearchResult::Reference { library, .. }
+            | CrdSearchResult::Manual { library, .. }
+            | CrdSearchResult::Collection { library, .. } => *library = entry,
+            CrdSearchResult::Profile { .. } | CrdSearchResult::Malformed { .. } => {}
+        }
+    }
+
+    /// 事例作成日・調べ方作成日
+    pub fn created_at(&self) -> Option<&CrdDate> {
+        match self {
+            CrdSearchResult::Reference { created_at, .. }
+            | CrdSearchResult::Manual { created_at, .. } => Some(created_at),
+            _ => None,
+        }
+    }
+
+    /// 最終更新日時
+    pub fn lst_date(&self) -> Option<&CrdDateTime> {
+        match self {
+            CrdSearchResult::Reference { system, .. }
+            | CrdSearchResult::Manual { system, .. }
+            | CrdSearchResult::Collection { system, .. } => Some(&system.lst_date),
+            CrdSearchResult::Profile { system, .. } => Some(&system.lst_date),
+            CrdSearchResult::Malformed { lst_date, .. } => lst_date.as_ref(),
+        }
+    }
+}
+
+/// ダイジェストの見出しの最大文字数
+const DIGEST_TITLE_LEN: usize = 80;
+
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct RecentUpdatesResponse {
+    /// 期間内のヒット件数
+    pub hit_count: i32,
+    /// 取得したレコード数
+    pub records_fetched: usize,
+    /// 取得上限に達し、期間内の一部のレコードを取得していない場合はtrue
+    pub truncated: bool,
+    /// 提供館ごとのダイジェスト(件数の多い順)
+    pub libraries: Vec<LibraryDigest>,
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct LibraryDigest {
+    /// 提供館コード
+    pub lib_id: String,
+    /// 提供館名
+    pub lib_name: String,
+    /// 期間内に登録・更新されたレコード数
+    pub count: usize,
+    /// レコード一覧(最終更新日時の新しい順)
+    pub items: Vec<DigestItem>,
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct DigestItem {
+    /// レコードの種類
+    #[serde(rename = "type")]
+    pub ty: ReqType,
+    /// 登録番号
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sys_id: Option<String>,
+    /// 見出し(質問・調査テーマ・コレクション名・図書館名)。長いものは省略する。
+    pub title: String,
+    pub url: String,
+    /// 登録日時
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reg_date: Option<CrdDateTime>,
+    /// 最終更新日時
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lst_date: Option<CrdDateTime>,
+}
+
+impl From<&CrdSearchResult> for DigestItem {
+    fn from(value: &CrdSearchResult) -> Self {
+        let mut title: String = value.title().chars().take(DIGEST_TITLE_LEN).collect();
+        if title.len() < value.title().len() {
+            title.push('…');
+        }
+        DigestItem {
+            ty: value.kind(),
+            sys_id: value.sys_id().map(str::to_string),
+            title,
+            url: value.url().to_string(),
+            reg_date: value.reg_date().cloned(),
+            lst_date: value.lst_date().cloned(),
+        }
+    }
+}
+
+impl RecentUpdatesResponse {
+    pub fn new(response: CrdSearchResponse) -> Self {
+        let mut libraries: Vec<LibraryDigest> = Vec::new();
+        for result in &response.results {
+            let item = DigestItem::from(result);
+            match libraries.iter_mut().find(|l| l.lib_id == result.lib_id()) {
+                Some(library) => library.items.push(item),
+                None => libraries.push(LibraryDigest {
+                    lib_id: result.lib_id().to_string(),
+                    lib_name: result.lib_name().to_string(),
+                    count: 0,
+                    items: vec![item],
+                }),
+            }
+        }
+        for library in &mut libraries {
+            library.count = library.items.len();
+            library.items.sort_by_key(|item| {
+                std::cmp::Reverse(item.lst_date.as_ref().and_then(|date| date.value))
+            });
+        }
+        libraries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.lib_id.cmp(&b.lib_id)));
+
+        RecentUpdatesResponse {
+            hit_count: response.hit_count,
+            records_fetched: response.results.len(),
+            truncated: (response.results.len() as i32) < response.hit_count,
+            libraries,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct ExportResponse {
+    /// ヒット件数
+    pub hit_count: i32,
+    /// 出力したレコード数
+    pub records: usize,
+    /// 出力したファイル、または出力内容
+    pub files: Vec<ExportedFile>,
+    /// 読み込めなかったレコードの警告
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct ExportedFile {
+    /// 出力に含まれるレコードの種類。JSONLでは種類を混在して出力するため省略する。
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub ty: Option<ReqType>,
+    /// 出力先のファイルパス
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// 出力内容(出力先の指定がない場合)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct BibliographyResponse {
+    /// 参考資料を集めたレコード数
+    pub records: usize,
+    /// 重複を除いた参考資料の件数
+    pub entries: usize,
+    /// 出力内容
+    pub content: String,
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct LocalSearchResponse {
+    /// ローカルミラー内のヒット件数
+    pub hit_count: i64,
+    /// 関連度の高い順の検索結果
+    pub results: Vec<LocalSearchHit>,
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct LocalSearchHit {
+    /// 関連度(BM25)。値が大きいほど検索語との関連が強い。
+    pub score: f64,
+    pub record: CrdSearchResult,
+}
+
+#[cfg(feature = "embeddings")]
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct SimilarCasesResponse {
+    /// 類似度の高い順のレファレンス事例
+    pub results: Vec<SimilarCase>,
+}
+
+#[cfg(feature = "embeddings")]
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct SimilarCase {
+    /// コサイン類似度(-1〜1)。値が大きいほど類似している。
+    pub similarity: f32,
+    pub record: CrdSearchResult,
+}
+
+/// NDCの下位区分
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct NdcNode {
+    /// 分類記号
+    pub code: String,
+    /// 見出し
+    pub label: String,
+    /// この分類記号で前方一致検索したときのヒット件数
+    pub hit_count: i32,
+}
+
+/// NDCの区分ごとのヒット件数
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct BrowseNdcResponse {
+    /// 一覧した分類記号。空文字列の場合は類の一覧。
+    pub code: String,
+    /// 一覧した分類記号の見出し
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// 見出しに使ったNDCの版
+    pub edition: NdcEdition,
+    /// 見出しが9版の表からの近似(8版・10版)の場合はtrue
+    pub approximate: bool,
+    /// 下位区分
+    pub children: Vec<NdcNode>,
+}
+
+/// 館種の出力形式
+#[derive(Serialize, JsonSchema)]
+struct LibTypeValue {
+    /// 館種コード(例 21)
+    code: String,
+    /// 館種コードのデコード値(例 公共図書館(都道府県立))。館種コード表にないコードの場合は省略する。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+/// 館種を館種コードとデコード値の組として出力する。
+fn serialize_lib_type<S: Serializer>(ty: &LibType, serializer: S) -> Result<S::Ok, S::Error> {
+    LibTypeValue {
+        code: ty.code().to_string(),
+        label: ty.label().map(str::to_string),
+    }
+    .serialize(serializer)
+}
+
+/// 参加館の連絡先
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct LibraryEntry {
+    /// 図書館コード
+    pub lib_id: String,
+    /// 図書館名（正式）
+    pub name: String,
+    /// 図書館名（略式）
+    pub abbr: String,
+    /// 館種
+    #[serde(serialize_with = "serialize_lib_type")]
+    #[schemars(with = "LibTypeValue")]
+    pub library_type: LibType,
+    /// 住所(郵便番号・都道府県・市区町村・丁目・番地)
+    pub address: String,
+    /// 電話番号
+    pub phone: String,
+    /// 図書館URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// ISIL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isil: Option<String>,
+}
+
+impl LibraryEntry {
+    /// 参加館プロファイルから連絡先をまとめる。参加館プロファイル以外は `None` を返す。
+    pub fn new(result: &CrdSearchResult) -> Option<LibraryEntry> {
+        let CrdSearchResult::Profile {
+            library_type,
+            library_name,
+            library_name_abbr,
+            zip_code,
+            address_prefecture,
+            address_city,
+            address_street,
+            tel1,
+            homepage,
+            isil,
+            system,
+            ..
+        } = result
+        else {
+            return None;
+        };
+        let address = format!(
+            "〒{} {}{}{}",
+            zip_code, address_prefecture, address_city, address_street
+        );
+        Some(LibraryEntry {
+            lib_id: system.lib_id.clone(),
+            name: library_name.clone(),
+            abbr: library_name_abbr.clone(),
+            library_type: library_type.clone(),
+            address,
+            phone: tel1.clone(),
+            url: homepage.clone(),
+            isil: isil.clone(),
+        })
+    }
+}
+
+/// 参加館の検索結果
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct FindLibrariesResponse {
+    /// ヒット件数
+    pub hit_count: i32,
+    /// 参加館の一覧
+    pub libraries: Vec<LibraryEntry>,
+}
+
+impl From<CrdSearchResponse> for FindLibrariesResponse {
+    fn from(value: CrdSearchResponse) -> Self {
+        FindLibrariesResponse {
+            hit_count: value.hit_count,
+            libraries: value.results.iter().filter_map(LibraryEntry::new).collect(),
+        }
+    }
+}
+
+/// ISILの検証結果
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct IsilCheck {
+    /// 指定されたISIL
+    pub value: String,
+    /// 形式が正しい場合はtrue
+    pub valid: bool,
+    /// 接頭辞を大文字にしたISIL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized: Option<String>,
+    /// 形式が正しくない場合の理由
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// ISILの検証・参加館の照会結果
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct IsilResponse {
+    /// isilを指定した場合の検証結果
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check: Option<IsilCheck>,
+    /// ヒット件数
+    pub hit_count: i32,
+    /// 該当する参加館
+    pub libraries: Vec<LibraryEntry>,
+}
+
+/// 集計の項目ごとの件数
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct FacetCount {
+    /// 値
+    pub value: String,
+    /// 値の見出し(NDCの見出し・提供館名)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// 件数
+    pub count: usize,
+}
+
+/// 解決・未解決の件数
+#[derive(Serialize, JsonSchema, Debug, Clone, Default)]
+pub struct SolutionCount {
+    /// 解決
+    pub resolved: usize,
+    /// 未解決
+    pub unresolved: usize,
+    /// 解決・未解決の値がない、または判定できない
+    pub unknown: usize,
+    /// 解決・未解決が判明している事例のうち、解決済みの割合(0〜1)。該当する事例がない場合は `None`。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_ratio: Option<f64>,
+}
+
+/// 検索結果の集計
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct FacetsResponse {
+    /// ヒット件数
+    pub hit_count: i32,
+    /// 集計したレコード数
+    pub records_analyzed: usize,
+    /// 取得上限に達し、一部のレコードを集計していない場合はtrue
+    pub truncated: bool,
+    /// NDC分類(要目の3桁)ごとの件数。件数の多い順。8版・10版の見出しは9版の表からの近似。
+    pub ndc: Vec<FacetCount>,
+    /// 調査種別ごとの件数
+    pub survey_type: Vec<FacetCount>,
+    /// 内容種別ごとの件数
+    pub content_type: Vec<FacetCount>,
+    /// 質問者区分ごとの件数
+    pub questioner_type: Vec<FacetCount>,
+    /// 提供館(図書館コード)ごとの件数
+    pub libraries: Vec<FacetCount>,
+    /// レファレンス事例の解決・未解決の件数
+    pub solution: SolutionCount,
+    /// 事例作成年ごとの件数。年の古い順。
+    pub created_year: Vec<FacetCount>,
+}
+
+/// 値ごとに件数を数える。初出順を保ったまま数え、件数の多い順に `top` 件を返す。
+#[derive(Default)]
+struct Counter {
+    counts: Vec<(String, Option<String>, usize)>,
+}
+
+impl Counter {
+    fn add(&mut self, value: &str, label: impl FnOnce() -> Option<String>) {
+        let value = value.trim();
+        if value.is_empty() {
+            return;
+        }
+        match self.counts.iter_mut().find(|(v, _, _)| v == value) {
+            Some((_, _, count)) => *count += 1,
+            None => self.counts.push((value.to_string(), label(), 1)),
+        }
+    }
+
+    fn into_top(mut self, top: usize) -> Vec<FacetCount> {
+        self.counts
+            .sort_by_key(|(_, _, count)| std::cmp::Reverse(*count));
+        self.counts.truncate(top);
+        self.into_facets()
+    }
+
+    fn into_facets(self) -> Vec<FacetCount> {
+        self.counts
+            .into_iter()
+            .map(|(value, label, count)| FacetCount {
+                value,
+                label,
+                count,
+            })
+            .collect()
+    }
+}
+
+/// 作成日の年。読み取れない場合は空文字列とし、集計しない。
+fn year(date: &CrdDate) -> String {
+    date.value
+        .map(|date| date.year().to_string())
+        .unwrap_or_default()
+}
+
+impl FacetsResponse {
+    /// 検索結果を集計する。各項目は件数の多い順に `top` 件までとする。
+    pub fn new(response: CrdSearchResponse, top: usize) -> Self {
+        let mut ndc = Counter::default();
+        let mut survey_type = Counter::default();
+        let mut content_type = Counter::default();
+        let mut questioner_type = Counter::default();
+        let mut libraries = Counter::default();
+        let mut solution = SolutionCount::default();
+        let mut created_year = Counter::default();
+
+        for result in &response.results {
+            for class in result.classes() {
+                let Some(value) = class.value.as_deref() else {
+                    continue;
+                };
+                let value: String = value.nfkc().collect();
+                let section: String = value
+                    .split('.')
+                    .next()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(3)
+                    .collect();
+                ndc.add(&section, || {
+                    NdcEdition::from_version(class.version.as_deref())
+                        .and_then(|edition| ndc::describe(edition, &section))
+                });
+            }
+            libraries.add(result.lib_id(), || Some(result.lib_name().to_string()));
+            match result {
+                CrdSearchResult::Reference {
+                    solution: state,
+                    survey_type: st,
+                    content_type: ct,
+                    questioner_type: qt,
+                    created_at,
+                    ..
+                } => {
+                    survey_type.add(st.as_deref().unwrap_or_default(), || None);
+                    content_type.add(ct.as_deref().unwrap_or_default(), || None);
+                    questioner_type.add(qt.as_deref().unwrap_or_default(), || None);
+                    match state {
+                        Solution::Resolved => solution.resolved += 1,
+                        Solution::Unresolved => solution.unresolved += 1,
+                        Solution::Unknown(_) => solution.unknown += 1,
+                    }
+                    created_year.add(&year(created_at), || None);
+                }
+                CrdSearchResult::Manual { created_at, .. } => {
+                    created_year.add(&year(created_at), || None);
+                }
+                CrdSearchResult::Collection { .. }
+                | CrdSearchResult::Profile { .. }
+                | CrdSearchResult::Malformed { .. } => {}
+            }
+        }
+
+        let total = solution.resolved + solution.unresolved;
+        if total > 0 {
+            solution.resolved_ratio = Some(solution.resolved as f64 / total as f64);
+        }
+        created_year.counts.sort_by(|a, b| a.0.cmp(&b.0));
+
+        FacetsResponse {
+            hit_count: response.hit_count,
+            records_analyzed: response.results.len(),
+            truncated: (response.results.len() as i32) < response.hit_count,
+            ndc: ndc.into_top(top),
+            survey_type: survey_type.into_top(top),
+            content_type: content_type.into_top(top),
+            questioner_type: questioner_type.into_top(top),
+            libraries: libraries.into_top(top),
+            solution,
+            created_year: created_year.into_facets(),
+        }
+    }
+}
+
+/// 未解決のレファレンス事例
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct UnresolvedQuestion {
+    /// 登録番号
+    pub sys_id: String,
+    pub url: String,
+    /// 質問
+    pub question: String,
+    /// 事前調査事項
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_survey: Option<String>,
+    /// 事例作成日
+    pub created_at: CrdDate,
+    /// 事例作成日からの経過日数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_days: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keywords: Option<Vec<String>>,
+    /// 分類
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub classes: Option<Vec<ClassResult>>,
+    /// 提供館コード
+    pub lib_id: String,
+    /// 提供館名
+    pub lib_name: String,
+}
+
+/// 未解決のレファレンス事例の一覧
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct UnresolvedResponse {
+    /// ヒット件数
+    ///
+    /// 都道府県を指定した場合は、その都道府県の参加館の図書館名で検索したヒット件数の合計で、
+    /// 同じ名前を含む他の都道府県の館の事例を含むことがある。
+    pub hit_count: i32,
+    /// 並べ替えのために取得したレコード数
+    pub records_fetched: usize,
+    /// 取得したレコードのうち、条件(都道府県を含む)に一致した未解決の事例の数
+    pub matched: usize,
+    /// 取得上限に達し、一部のレコードを並べ替えの対象にしていない場合はtrue
+    ///
+    /// この場合、並び順は取得した範囲(CRDの検索結果の先頭から)での順であり、全体での順ではない。
+    pub truncated: bool,
+    /// 未解決の事例(経過日数の順)
+    pub questions: Vec<UnresolvedQuestion>,
+}
+
+impl UnresolvedResponse {
+    /// 未解決の事例を事例作成日の順に並べ、`limit` 件を返す。
+    ///
+    /// `lib_ids` を指定した場合は、その提供館の事例のみを対象とする。
+    pub fn new(
+        response: CrdSearchResponse,
+        today: NaiveDate,
+        order: AgeOrder,
+        limit: usize,
+        lib_ids: Option<&[String]>,
+    ) -> Self {
+        let records_fetched = response.results_returned.max(0) as usize;
+        let truncated = response.has_more;
+        let mut questions = response
+            .results
+            .into_iter()
+            .filter(|result| lib_ids.is_none_or(|ids| ids.iter().any(|id| id == result.lib_id())))
+            .filter_map(|result| match result {
+                CrdSearchResult::Reference {
+                    url,
+                    question,
+                    created_at,
+                    keywords,
+                    classes,
+                    pre_survey,
+                    system,
+                    ..
+                } => Some(UnresolvedQuestion {
+                    sys_id: system.sys_id,
+                    url,
+                    question,
+                    pre_survey,
+                    age_days: created_at.value.map(|date| (today - date).num_days()),
+                    created_at,
+                    keywords,
+                    classes,
+                    lib_id: system.lib_id,
+                    lib_name: system.lib_name,
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // 作成日が不明な事例は並び順によらず末尾とする
+        questions.sort_by(|a, b| match (a.created_at.value, b.created_at.value) {
+            (Some(a), Some(b)) => match order {
+                AgeOrder::Oldest => a.cmp(&b),
+                AgeOrder::Newest => b.cmp(&a),
+            },
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+        let matched = questions.len();
+        questions.truncate(limit);
+        UnresolvedResponse {
+            hit_count: response.hit_count,
+            records_fetched,
+            matched,
+            truncated,
+            questions,
+        }
+    }
+}
+
+/// キーワードの候補
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct SuggestKeywordsResponse {
+    /// 語彙の元にしたレコード数
+    pub records_indexed: usize,
+    /// 語彙に含まれるキーワードの数
+    pub keywords_indexed: usize,
+    pub suggestions: Vec<KeywordSuggestion>,
+}
+
+/// 部分的な語を含むキーワード
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct KeywordSuggestion {
+    pub keyword: String,
+    /// このキーワードが付与されたレコード数
+    pub count: usize,
+    /// 同じレコードに付与されることの多いキーワード
+    pub related: Vec<RelatedKeyword>,
+    /// このキーワードで検索するための検索式
+    pub query: String,
+}
+
+/// 同じレコードに付与されたキーワード
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+pub struct RelatedKeyword {
+    pub keyword: String,
+    /// 同じレコードに付与された回数
+    pub count: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CrdSearchResponse, FacetsResponse, MultiSearchResponse, SearchSection, UnresolvedResponse,
+    };
+    use crate::crd::CrdResultSet;
+    use crate::req::{AgeOrder, ReqType, SortKey, SortOrder};
+    use chrono::NaiveDate;
+    use rmcp::ErrorData;
+
+    const REFERENCE_XML: &str = include_str!("fixtures/reference.xml");
+
+    fn response() -> CrdSearchResponse {
+        let set = CrdResultSet::parse(REFERENCE_XML).unwrap();
+        Result::<CrdSearchResponse, ErrorData>::from(set).unwrap()
+    }
+
+    #[test]
+    fn test_facets() {
+        let facets = FacetsResponse::new(response(), 20);
+        assert_eq!(facets.records_analyzed, 2);
+        assert!(!facets.truncated);
+        assert_eq!(facets.ndc[0].value, "211");
+        assert_eq!(
+            facets.ndc[0].label.as_deref(),
+            Some("歴史 > 日本史 > 北海道地方")
+        );
+        assert_eq!(facets.ndc[1].value, "291");
+        assert_eq!(facets.survey_type[0].value, "文献紹介");
+        assert_eq!(facets.libraries.len(), 2);
+        assert_eq!(facets.solution.resolved, 1);
+        assert_eq!(facets.solution.unresolved, 1);
+        assert_eq!(facets.solution.resolved_ratio, Some(0.5));
+        assert_eq!(facets.created_year[0].value, "2023");
+        assert_eq!(facets.created_year[0].count, 2);
+
+        // 全角の数字を含む分類記号は半角に正規化してから区切る
+        let xml = REFERENCE_XML.replace(">211</classes>", ">2１0.5</classes>");
+        let set = CrdResultSet::parse(&xml).unwrap();
+        let response = Result::<CrdSearchResponse, ErrorData>::from(set).unwrap();
+        let facets = FacetsResponse::new(response, 20);
+        let facet = facets
+            .ndc
+            .iter()
+            .find(|facet| facet.value == "210")
+            .unwrap();
+        assert_eq!(facet.label.as_deref(), Some("歴史 > 日本史"));
+    }
+
+    #[test]
+    fn test_malformed_warnings() {
+        let xml = REFERENCE_XML.replacen("<file-num>1</file-num>", "<file-num>x</file-num>", 1);
+        let set = CrdResultSet::parse(&xml).unwrap();
+        let response = Result::<CrdSearchResponse, ErrorData>::from(set).unwrap();
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.warnings.len(), 1);
+        assert!(response.warnings[0].starts_with("2件目のレコード(登録番号: 1000300002)"));
+        assert_eq!(response.results[1].sys_id(), Some("1000300002"));
+        assert_eq!(response.results[1].kind(), ReqType::Reference);
+
+        let xml = REFERENCE_XML.replacen("<solution>1</solution>", "<solution>保留</solution>", 1);
+        let set = CrdResultSet::parse(&xml).unwrap();
+        let response = Result::<CrdSearchResponse, ErrorData>::from(set).unwrap();
+        assert_eq!(response.warnings.len(), 1);
+        assert!(response.warnings[0].ends_with("のsolutionの値を判定できませんでした: 保留"));
+        assert_eq!(response.results[1].unknown_flags(), [("solution", "保留")]);
+    }
+
+    #[test]
+    fn test_from_sections() {
+        let sections = vec![
+            SearchSection {
+                ty: ReqType::Reference,
+                response: Some(response()),
+                error: None,
+            },
+            SearchSection {
+                ty: ReqType::Profile,
+                response: None,
+                error: Some("検索式が正しくありません".to_string()),
+            },
+        ];
+        let merged = CrdSearchResponse::from_sections(sections);
+        assert_eq!(merged.hit_count, 2);
+        assert_eq!(merged.results_returned, 2);
+        assert!(merged.results.is_empty());
+
+        let value = serde_json::to_value(&merged).unwrap();
+        assert_eq!(value["sections"][0]["type"], "reference");
+        assert_eq!(value["sections"][0]["hit_count"], 2);
+        assert_eq!(value["sections"][1]["error"], "検索式が正しくありません");
+    }
+
+    #[test]
+    fn test_multi_search() {
+        let multi = MultiSearchResponse::new(
+            vec![
+                ("公共".to_string(), Ok(response())),
+                ("大学".to_string(), Ok(response())),
+                (
+                    "専門".to_string(),
+                    Err("検索式が正しくありません".to_string()),
+                ),
+            ],
+            true,
+        );
+        assert_eq!(multi.searches.len(), 3);
+        assert!(multi.searches["専門"].error.is_some());
+        let merged = multi.merged.unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].labels, ["公共", "大学"]);
+        assert_eq!(merged[0].result.sys_id(), response().results[0].sys_id());
+
+        let multi = MultiSearchResponse::new(vec![("公共".to_string(), Ok(response()))], false);
+        assert!(multi.merged.is_none());
+    }
+
+    #[test]
+    fn test_sorted() {
+        let sorted = response().sorted(SortKey::CreatedAt, SortOrder::Asc, 0, 10);
+        assert_eq!(sorted.results[0].sys_id(), Some("1000300002"));
+        assert_eq!(sorted.sort.unwrap().records_sorted, 2);
+
+        let sorted = response().sorted(SortKey::LstDate, SortOrder::Desc, 1, 1);
+        assert_eq!(sorted.cursor_position, 1);
+        assert_eq!(sorted.results_returned, 1);
+        assert_eq!(sorted.results[0].sys_id(), Some("1000300001"));
+
+        let sorted = response().sorted(SortKey::LibName, SortOrder::Asc, 0, 10);
+        assert_eq!(sorted.results[0].lib_name(), "函館市中央図書館");
+    }
+
+    #[test]
+    fn test_unresolved() {
+        let today = NaiveDate::from_ymd_opt(2023, 4, 10).unwrap();
+        let unresolved = UnresolvedResponse::new(response(), today, AgeOrder::Oldest, 1, None);
+        assert_eq!(unresolved.records_fetched, 2);
+        assert_eq!(unresolved.matched, 2);
+        assert!(!unresolved.truncated);
+        assert_eq!(unresolved.questions.len(), 1);
+        assert_eq!(unresolved.questions[0].sys_id, "1000300002");
+        assert_eq!(unresolved.questions[0].age_days, Some(31));
+
+        let newest = UnresolvedResponse::new(response(), today, AgeOrder::Newest, 10, None);
+        assert_eq!(newest.questions[0].sys_id, "1000300001");
+
+        let ids = vec!["2110002".to_string()];
+        let filtered = UnresolvedResponse::new(response(), today, AgeOrder::Oldest, 10, Some(&ids));
+        assert_eq!(filtered.questions.len(), 1);
+        assert_eq!(filtered.matched, 1);
+    }
+}
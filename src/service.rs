@@ -1,60 +1,942 @@
-use crate::req::CrdSearchRequest;
-use crate::res::CrdSearchResponse;
+use crate::completion;
+use crate::crd::RateLimiter;
+use crate::cursor::SearchCursor;
+use crate::date;
+use crate::export::{export, export_bibliography};
+use crate::library::{
+    LibraryCache, attach_libraries, libraries_in_prefecture, library_candidates, lookup_isil,
+    normalize_prefecture, quote, search_in_libraries,
+};
+use crate::mirror::Mirror;
+use crate::ndc::{self, NdcEdition};
+use crate::query::{Expander, Query};
+use crate::req::{
+    BibliographyRequest, BrowseNdcRequest, Condition, CrdSearchRequest, ExportRequest,
+    FacetsRequest, FindLibrariesRequest, IsbnSearchRequest, IsilRequest, LabeledSearchRequest,
+    LibGroup, LocalSearchRequest, MultiSearchRequest, RecentUpdatesRequest, ReqType,
+    ResultsNumByType, SearchCasesPrompt, SearchRequest, SortKey, SuggestKeywordsPrompt,
+    SuggestKeywordsRequest, UnresolvedRequest,
+};
+use crate::res::{
+    CrdSearchResponse, FacetsResponse, FindLibrariesResponse, MultiSearchResponse,
+    RecentUpdatesResponse, RelaxationReport, RelaxationStep, SearchSection,
+    SuggestKeywordsResponse, UnresolvedResponse,
+};
+use crate::vocabulary::VocabularyCache;
+use rmcp::handler::server::router::prompt::PromptRouter;
 use rmcp::handler::server::tool::ToolRouter;
 use rmcp::handler::server::wrapper::Parameters;
 use rmcp::model::{
-    CallToolResult, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
+    CallToolResult, CompleteRequestParam, CompleteResult, CompletionInfo, GetPromptRequestParam,
+    GetPromptResult, Implementation, ListPromptsResult, PaginatedRequestParam, PromptMessage,
+    PromptMessageRole, ProtocolVersion, Reference, ServerCapabilities, ServerInfo,
+};
+use rmcp::service::RequestContext;
+use rmcp::{
+    ErrorData, RoleServer, ServerHandler, prompt, prompt_handler, prompt_router, tool,
+    tool_handler, tool_router,
 };
-use rmcp::{ErrorData, ServerHandler, tool, tool_handler, tool_router};
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OnceCell};
+
+/// 並べ替えのために取得する最大件数
+const SORT_MAX_RECORDS: usize = 1000;
+/// 並べ替えのために取得したレコードを保持する検索条件の数
+const SORT_CACHE_MAX: usize = 8;
+/// multi_searchで一度に指定できる検索条件の最大件数
+const MULTI_SEARCH_MAX: usize = 10;
+/// exportで取得できる最大件数
+const EXPORT_MAX_RECORDS: u32 = 10000;
+/// bibliographyで参考資料を集める最大レコード数
+const BIBLIOGRAPHY_MAX_RECORDS: u32 = 2000;
+/// キーワードの候補ごとに返す関連キーワードの最大件数
+const SUGGEST_RELATED_MAX: usize = 5;
+/// 検索式の緩和を試みる最大の段階数
+const RELAX_MAX_STEPS: usize = 8;
+/// `type = "all"` の検索で区分を分ける種類
+const SECTION_TYPES: [ReqType; 4] = [
+    ReqType::Reference,
+    ReqType::Manual,
+    ReqType::Collection,
+    ReqType::Profile,
+];
+
+/// 並べ替えのために取得したレコードのキャッシュ
+///
+/// カーソルの指紋ごとに保持し、続きのページの取得ではCRDを検索し直さない。
+/// `SORT_CACHE_MAX` 件を超えた場合は古い検索条件から破棄する。
+#[derive(Debug, Clone, Default)]
+pub struct SortCache(Arc<Mutex<VecDeque<SortedRecords>>>);
+
+/// カーソルの指紋と、並べ替えのために取得したレコード
+type SortedRecords = (String, Arc<CrdSearchResponse>);
 
 #[derive(Debug, Clone)]
 pub struct CrdService {
     pub http: reqwest::Client,
+    pub limiter: RateLimiter,
+    /// ローカルミラーのSQLiteデータベース
+    pub mirror_path: Option<PathBuf>,
+    /// 提供館の参加館プロファイルのキャッシュ
+    pub library_cache: LibraryCache,
+    /// 検索式の表記ゆれ・同義語の展開
+    pub expander: Arc<Expander>,
+    /// 取得したレコード・ローカルミラーのキーワードの語彙
+    pub vocabulary: VocabularyCache,
+    /// ローカルミラーの参加館の図書館コード・図書館名。図書館コードの補完のため初回に読み込む。
+    pub mirror_libraries: Arc<OnceCell<Vec<(String, String, String)>>>,
+    /// 並べ替えのために取得したレコード
+    pub sort_cache: SortCache,
+    /// 類似事例の検索に使う埋め込みモデル。初回の使用時に読み込む。
+    #[cfg(feature = "embeddings")]
+    embedder: std::sync::Arc<std::sync::Mutex<Option<crate::mirror::Embedder>>>,
     tool_router: ToolRouter<Self>,
+    prompt_router: PromptRouter<Self>,
 }
 
 impl CrdService {
     pub fn new() -> CrdService {
+        let tool_router = Self::tool_router();
+        #[cfg(feature = "embeddings")]
+        let tool_router = tool_router + Self::embedding_router();
         CrdService {
             http: reqwest::Client::new(),
-            tool_router: Self::tool_router(),
+            limiter: RateLimiter::default(),
+            mirror_path: None,
+            library_cache: LibraryCache::default(),
+            expander: Arc::new(Expander::bundled()),
+            vocabulary: VocabularyCache::default(),
+            mirror_libraries: Default::default(),
+            sort_cache: SortCache::default(),
+            #[cfg(feature = "embeddings")]
+            embedder: Default::default(),
+            tool_router,
+            prompt_router: Self::prompt_router(),
+        }
+    }
+
+    pub fn with_mirror(mut self, path: Option<PathBuf>) -> CrdService {
+        self.mirror_path = path;
+        self
+    }
+
+    /// 同梱の表記ゆれの辞書に、利用者の同義語辞書を追加する。
+    pub fn with_synonyms(mut self, path: Option<PathBuf>) -> anyhow::Result<CrdService> {
+        if let Some(path) = path {
+            self.expander = Arc::new(Expander::bundled().with_dictionary(&path)?);
+        }
+        Ok(self)
+    }
+
+    /// 検索式を表記ゆれ・同義語で展開し、展開後の検索式を返す。検索式がない場合は `None` を返す。
+    pub fn expand_query(&self, search: &mut CrdSearchRequest) -> Result<Option<String>, ErrorData> {
+        let Some(query) = &search.condition.query else {
+            return Ok(None);
+        };
+        let expanded = self
+            .expander
+            .expand_cql(query)
+            .map_err(|e| ErrorData::invalid_params(e, None))?;
+        search.condition.query = Some(expanded.clone());
+        Ok(Some(expanded))
+    }
+
+    /// ローカルミラーを開いて処理を行う。
+    pub async fn with_mirror_db<T, F>(&self, f: F) -> Result<T, ErrorData>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Mirror) -> anyhow::Result<T> + Send + 'static,
+    {
+        let Some(path) = self.mirror_path.clone().filter(|path| path.exists()) else {
+            return Err(ErrorData::invalid_request(
+                "ローカルミラーが設定されていません。syncサブコマンドで作成し、--db または CRD_MIRROR_PATH で指定してください。",
+                None,
+            ));
+        };
+        tokio::task::spawn_blocking(move || f(&Mirror::open(&path)?))
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))
+    }
+
+    /// 検索条件に一致するレコードを、`max_records` 件を上限としてページングしながら取得する。
+    ///
+    /// 返却する `CrdSearchResponse` の `results_returned` は取得した全ページの合計件数となる。
+    pub async fn search_all(
+        &self,
+        mut request: CrdSearchRequest,
+        max_records: usize,
+    ) -> Result<CrdSearchResponse, ErrorData> {
+        request
+            .validate()
+            .map_err(|e| ErrorData::invalid_params(e, None))?;
+        let start = request.results_get_position.unwrap_or(0);
+        let mut position = start;
+        let mut hit_count = 0;
+        let mut results = Vec::new();
+        let mut warnings = Vec::new();
+        while results.len() < max_records {
+            request.results_get_position = Some(position);
+            request.results_num = (max_records - results.len()).min(100) as i8;
+            let page = self
+                .crd_search(request.clone())
+                .await
+                .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+            let page = Result::<CrdSearchResponse, ErrorData>::from(page)?;
+            hit_count = page.hit_count;
+            position = page.cursor_position + page.results_returned;
+            let returned = page.results.len();
+            results.extend(page.results);
+            warnings.extend(page.warnings);
+            if returned == 0 || position >= hit_count {
+                break;
+            }
         }
+        Ok(CrdSearchResponse {
+            hit_count,
+            cursor_position: start,
+            results_returned: results.len() as i32,
+            has_more: position < hit_count,
+            next_cursor: None,
+            results,
+            warnings,
+            sort: None,
+            sections: Vec::new(),
+            expanded_query: None,
+            relaxation: None,
+        })
+    }
+
+    /// 先頭から `SORT_MAX_RECORDS` 件までを取得して並べ替え、カーソルの位置から `results_num` 件を返す。
+    ///
+    /// 取得したレコードはカーソルの指紋ごとに `sort_cache` に保持し、続きのページでは再利用する。
+    pub async fn search_sorted(
+        &self,
+        cursor: &SearchCursor,
+        sort_by: SortKey,
+    ) -> Result<CrdSearchResponse, ErrorData> {
+        let request = cursor.request();
+        let position = request.results_get_position.unwrap_or(0).max(0) as usize;
+        let num = request.results_num.max(0) as usize;
+        let cached = self
+            .sort_cache
+            .0
+            .lock()
+            .await
+            .iter()
+            .find(|(fingerprint, _)| fingerprint == cursor.fingerprint())
+            .map(|(_, response)| response.clone());
+        let response = match cached {
+            Some(response) => response,
+            None => {
+                let request = CrdSearchRequest {
+                    results_get_position: None,
+                    ..request
+                };
+                let response = Arc::new(self.search_all(request, SORT_MAX_RECORDS).await?);
+                let mut cache = self.sort_cache.0.lock().await;
+                cache.retain(|(fingerprint, _)| fingerprint != cursor.fingerprint());
+                cache.push_back((cursor.fingerprint().to_string(), response.clone()));
+                while cache.len() > SORT_CACHE_MAX {
+                    cache.pop_front();
+                }
+                response
+            }
+        };
+        Ok(CrdSearchResponse::clone(&response).sorted(
+            sort_by,
+            cursor.order().unwrap_or(sort_by.default_order()),
+            position,
+            num,
+        ))
+    }
+
+    /// カーソルの位置から1ページを取得し、続きがある場合は `next_cursor` を付与する。
+    pub async fn search_page(&self, cursor: &SearchCursor) -> Result<CrdSearchResponse, ErrorData> {
+        cursor
+            .request()
+            .validate()
+            .map_err(|e| ErrorData::invalid_params(e, None))?;
+        let mut response = match cursor.sort_by().filter(|key| *key != SortKey::Relevance) {
+            Some(sort_by) => self.search_sorted(cursor, sort_by).await?,
+            None => {
+                let k = self
+                    .crd_search(cursor.request())
+                    .await
+                    .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+                Result::<CrdSearchResponse, ErrorData>::from(k)?
+            }
+        };
+        if response.has_more {
+            let next = response.cursor_position + response.results_returned;
+            response.next_cursor = Some(cursor.with_offset(next).encode());
+        }
+        Ok(response)
+    }
+
+    /// `type = "all"` の検索を種類ごとに行い、種類ごとの区分として返す。
+    ///
+    /// 種類ごとの検索結果返却件数は `results_num_by_type` で指定し、指定がない種類は `results_num` とする。
+    /// 検索式がその種類に対応していない場合などのエラーは、その区分のみのエラーとする。
+    pub async fn search_sections(
+        &self,
+        cursor: &SearchCursor,
+        results_num_by_type: &ResultsNumByType,
+    ) -> Result<CrdSearchResponse, ErrorData> {
+        let mut sections = Vec::new();
+        for ty in SECTION_TYPES {
+            let results_num = results_num_by_type
+                .get(ty)
+                .unwrap_or(cursor.request().results_num);
+            if results_num == 0 {
+                continue;
+            }
+            let section = match self.search_page(&cursor.for_type(ty, results_num)).await {
+                Ok(response) => SearchSection {
+                    ty,
+                    response: Some(response),
+                    error: None,
+                },
+                Err(e) => SearchSection {
+                    ty,
+                    response: None,
+                    error: Some(e.message.to_string()),
+                },
+            };
+            sections.push(section);
+        }
+        Ok(CrdSearchResponse::from_sections(sections))
+    }
+
+    /// カーソルの検索条件で検索する。`type = "all"` の場合は種類ごとの区分として返す。
+    pub async fn search_cursor(
+        &self,
+        cursor: &SearchCursor,
+        results_num_by_type: &ResultsNumByType,
+    ) -> Result<CrdSearchResponse, ErrorData> {
+        if cursor.request().ty == ReqType::All {
+            self.search_sections(cursor, results_num_by_type).await
+        } else {
+            self.search_page(cursor).await
+        }
+    }
+
+    /// ヒットしなかった検索式を段階的に緩和し、ヒットするまで検索し直す。
+    ///
+    /// ヒットした場合はその検索結果を、いずれの段階でもヒットしなかった場合は元の検索結果を返し、
+    /// 緩和の経過を `relaxation` に付与する。
+    pub async fn search_relaxed(
+        &self,
+        cursor: &SearchCursor,
+        results_num_by_type: &ResultsNumByType,
+        response: CrdSearchResponse,
+    ) -> Result<CrdSearchResponse, ErrorData> {
+        let Some(original_query) = cursor.request().condition.query else {
+            return Ok(response);
+        };
+        let mut query: Query = original_query
+            .parse()
+            .map_err(|e: String| ErrorData::invalid_params(e, None))?;
+        let mut steps = Vec::new();
+        let mut matched = None;
+        while steps.len() < RELAX_MAX_STEPS {
+            let Some((relaxation, relaxed)) = query.relax() else {
+                break;
+            };
+            query = relaxed;
+            let relaxed_cursor = cursor.with_query(query.to_string());
+            let (hit_count, error) = match self
+                .search_cursor(&relaxed_cursor, results_num_by_type)
+                .await
+            {
+                Ok(response) => {
+                    let hit_count = response.hit_count;
+                    if hit_count > 0 {
+                        matched = Some(response);
+                    }
+                    (Some(hit_count), None)
+                }
+                Err(e) => (None, Some(e.message.to_string())),
+            };
+            steps.push(RelaxationStep {
+                relaxation,
+                query: query.to_string(),
+                hit_count,
+                error,
+            });
+            if matched.is_some() {
+                break;
+            }
+        }
+        let report = RelaxationReport {
+            original_query,
+            matched_query: matched.as_ref().map(|_| query.to_string()),
+            steps,
+        };
+        let mut response = matched.unwrap_or(response);
+        response.relaxation = Some(report);
+        Ok(response)
+    }
+
+    /// ローカルミラーのキーワードを語彙に読み込む。ローカルミラーがない場合と読み込み済みの場合は何もしない。
+    pub async fn load_mirror_vocabulary(&self) -> Result<(), ErrorData> {
+        let exists = self.mirror_path.as_ref().is_some_and(|path| path.exists());
+        if !exists || self.vocabulary.0.lock().await.mirror_loaded {
+            return Ok(());
+        }
+        let sets = self.with_mirror_db(|mirror| mirror.keyword_sets()).await?;
+        let mut vocabulary = self.vocabulary.0.lock().await;
+        for (sys_id, keywords) in &sets {
+            vocabulary.add(sys_id, keywords);
+        }
+        vocabulary.mirror_loaded = true;
+        Ok(())
+    }
+
+    /// 語の一部を含むキーワードの候補を返す。
+    ///
+    /// 語彙に一致するキーワードがない場合は、CRDをキーワードで検索し、取得したレコードのキーワードを語彙に加える。
+    pub async fn suggest_keywords_for(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<SuggestKeywordsResponse, ErrorData> {
+        if query.trim().is_empty() {
+            return Err(ErrorData::invalid_params("queryを指定してください", None));
+        }
+        self.load_mirror_vocabulary().await?;
+        if self.vocabulary.0.lock().await.matches(query, 1).is_empty() {
+            let request = CrdSearchRequest {
+                ty: ReqType::All,
+                condition: Condition {
+                    query: Some(format!("keyword any {}", quote(query))),
+                    crt_date_from: None,
+                    crt_date_to: None,
+                    reg_date_from: None,
+                    reg_date_to: None,
+                    lst_date_from: None,
+                    lst_date_to: None,
+                },
+                lib_id: None,
+                lib_group: None,
+                lib_type: None,
+                results_get_position: None,
+                results_num: 100,
+            };
+            self.crd_search(request).await.map_err(into_error_data)?;
+        }
+        let vocabulary = self.vocabulary.0.lock().await;
+        Ok(SuggestKeywordsResponse {
+            records_indexed: vocabulary.record_count(),
+            keywords_indexed: vocabulary.keyword_count(),
+            suggestions: vocabulary.suggest(query, limit, SUGGEST_RELATED_MAX),
+        })
     }
 }
 
 #[tool_router]
 impl CrdService {
     #[tool(
-        description = "レファレンス協同データベースシステム(CRD)を検索する。各データを表示する際は、提供館名も明示してください。"
+        description = "レファレンス協同データベースシステム(CRD)を検索する。expand = trueの場合は、検索語を旧字体・新字体、ひらがな・カタカナ、全角・半角、送り仮名などの表記ゆれと同義語に展開して検索し、展開した検索式をexpanded_queryとして返す(ヒット件数が少ない場合に使用する)。relax = trueの場合は、ヒットしなかった検索式を段階的に緩和して検索し直し、ヒットした検索式と各段階のヒット件数をrelaxationとして返す。type = allの場合は、レファレンス事例・調べ方マニュアル・特別コレクション・参加館プロファイルの種類ごとの区分(sections)として返す。sort_byを指定すると、作成日・登録日時・最終更新日時・提供館名で並べ替えた結果を返す(「最新の事例」などを探す場合に使用する)。並べ替えは先頭から1000件までを取得した範囲で行い、sort.truncatedがtrueの場合は検索結果全体での順ではない。has_moreがtrueの場合は、next_cursorをcursorに指定すると続きを取得できる。各データを表示する際は、提供館名も明示してください。"
     )]
     pub async fn search(
         &self,
-        request: Parameters<CrdSearchRequest>,
+        request: Parameters<SearchRequest>,
     ) -> Result<CallToolResult, ErrorData> {
+        let mut request = request.0;
+        let results_num_by_type = request.results_num_by_type.clone().unwrap_or_default();
+        let relax = request.relax;
+        let expanded_query = match request.search.as_mut() {
+            Some(search) if request.expand => self.expand_query(search)?,
+            _ => None,
+        };
+        let cursor =
+            SearchCursor::from_request(request).map_err(|e| ErrorData::invalid_params(e, None))?;
+        let mut i = self.search_cursor(&cursor, &results_num_by_type).await?;
+        if relax && i.hit_count == 0 {
+            i = self
+                .search_relaxed(&cursor, &results_num_by_type, i)
+                .await?;
+        }
+        i.expanded_query = expanded_query;
+        if cursor.include_library() {
+            attach_libraries(self, &mut i.results).await?;
+            for section in &mut i.sections {
+                if let Some(response) = &mut section.response {
+                    attach_libraries(self, &mut response.results).await?;
+                }
+            }
+        }
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "複数の検索条件でCRDを同時に検索し、検索条件のlabelごとに結果を返す。同じ主題を図書館グループ(lib_group)や期間ごとに比較する場合などに使用する。merge = trueの場合は、全ての結果をsys_idで重複を除いてまとめたmergedも返す。各データを表示する際は、提供館名も明示してください。"
+    )]
+    pub async fn multi_search(
+        &self,
+        request: Parameters<MultiSearchRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let MultiSearchRequest {
+            searches,
+            merge,
+            expand,
+        } = request.0;
+        if searches.is_empty() || searches.len() > MULTI_SEARCH_MAX {
+            return Err(ErrorData::invalid_params(
+                format!("searchesは1件から{MULTI_SEARCH_MAX}件まで指定してください"),
+                None,
+            ));
+        }
+        let mut labels = HashSet::new();
+        if let Some(label) = searches.iter().find(|s| !labels.insert(s.label.as_str())) {
+            return Err(ErrorData::invalid_params(
+                format!("labelが重複しています: {}", label.label),
+                None,
+            ));
+        }
+        // 各検索は並行して行い、CRD APIへのアクセス間隔はRateLimiterで共有する。
+        let mut tasks = tokio::task::JoinSet::new();
+        for (index, LabeledSearchRequest { label, mut search }) in searches.into_iter().enumerate()
+        {
+            let service = self.clone();
+            tasks.spawn(async move {
+                let response = async {
+                    let expanded_query = match expand {
+                        true => service.expand_query(&mut search)?,
+                        false => None,
+                    };
+                    let cursor = SearchCursor::new(&search, None, None, false);
+                    let mut response = service.search_page(&cursor).await?;
+                    response.expanded_query = expanded_query;
+                    Ok::<_, ErrorData>(response)
+                }
+                .await;
+                (index, label, response.map_err(|e| e.message.to_string()))
+            });
+        }
+        let mut results = tasks.join_all().await;
+        results.sort_by_key(|(index, _, _)| *index);
+        let i = MultiSearchResponse::new(
+            results
+                .into_iter()
+                .map(|(_, label, response)| (label, response))
+                .collect(),
+            merge,
+        );
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "参考資料のISBNからその資料を参考資料とするレファレンス事例・調べ方マニュアルを検索する。ISBN-10・ISBN-13のどちらで登録されていても一致し、日本の出版者のISBNはハイフン付きの表記でも一致する。"
+    )]
+    pub async fn search_by_isbn(
+        &self,
+        request: Parameters<IsbnSearchRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let request = CrdSearchRequest::try_from(request.0)
+            .map_err(|e| ErrorData::invalid_params(e, None))?;
         let k = self
-            .crd_search(request.0)
+            .crd_search(request)
             .await
             .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
         let i = Result::<CrdSearchResponse, ErrorData>::from(k)?;
         Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
     }
+
+    #[tool(
+        description = "ISIL(図書館及び関連組織のための国際標準識別子、例 JP-1000001)の形式を検証し、ISILから参加館を、図書館コードから参加館のISILを照会する。lib_groupを指定すると、グループに含まれる参加館とISILの一覧を返す。他のデータセットとISILで突き合わせる際に使用する。"
+    )]
+    pub async fn isil(
+        &self,
+        request: Parameters<IsilRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let i = lookup_isil(self, request.0).await?;
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "NDC(日本十進分類法)の類・綱の下位区分の見出しと、区分ごとのヒット件数を返す。codeを省略すると類(0〜9)を一覧する。下位区分ごとにCRD APIを呼び出すため(最大10回)、応答に数秒かかる。8版・10版の見出しは9版の表からの近似。分野ごとの事例の分布の把握や、検索条件のndcの絞り込みに使用する。"
+    )]
+    pub async fn browse_ndc(
+        &self,
+        request: Parameters<BrowseNdcRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let i = ndc::browse(self, request.0).await?;
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "都道府県・市区町村・館種・キーワードからCRDの参加館を探し、図書館名・略称・住所・電話番号・URL・ISILの一覧を返す。「〇〇の近くで△△に詳しい図書館」のような問い合わせに使用する。"
+    )]
+    pub async fn find_libraries(
+        &self,
+        request: Parameters<FindLibrariesRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let request = CrdSearchRequest::try_from(request.0)
+            .map_err(|e| ErrorData::invalid_params(e, None))?;
+        let k = self
+            .crd_search(request)
+            .await
+            .map_err(|e| ErrorData::internal_error(e.to_string(), None))?;
+        let i = FindLibrariesResponse::from(Result::<CrdSearchResponse, ErrorData>::from(k)?);
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "指定した期間内に新しく登録・更新されたレコードを全件取得し、提供館ごとにまとめたダイジェストを返す。新着事例の定期的な確認に使用する。"
+    )]
+    pub async fn recent_updates(
+        &self,
+        request: Parameters<RecentUpdatesRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let max_records = check_max_records(request.0.max_records, 2000)?;
+        let request = CrdSearchRequest::try_from(request.0)
+            .map_err(|e| ErrorData::invalid_params(e, None))?;
+        let k = self.search_all(request, max_records).await?;
+        let i = RecentUpdatesResponse::new(k);
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "検索結果をページングしながら取得し、NDC分類・調査種別・内容種別・質問者区分・提供館ごとの件数、解決・未解決の割合、事例作成年ごとの件数を集計する。レファレンスサービスの統計・報告書の作成に使用する。"
+    )]
+    pub async fn facets(
+        &self,
+        request: Parameters<FacetsRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let FacetsRequest {
+            search,
+            max_records,
+            top,
+        } = request.0;
+        let max_records = check_max_records(max_records, 5000)?;
+        let k = self.search_all(search, max_records).await?;
+        let i = FacetsResponse::new(k, top as usize);
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "未解決(solution = 1)のレファレンス事例を、事例作成日の古い順(または新しい順)に返す。NDC分類の前方一致・キーワード・提供館の都道府県・事例作成日で絞り込める。都道府県は、その都道府県の参加館の図書館名を検索条件に加えて絞り込む。並べ替えはmax_records件までの取得した範囲で行い、truncatedがtrueの場合は全体での順ではない。協同での回答に参加できる事例を探すために使用する。"
+    )]
+    pub async fn unresolved_questions(
+        &self,
+        request: Parameters<UnresolvedRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let request = request.0;
+        let max_records = check_max_records(request.max_records, 5000)?;
+        let search =
+            CrdSearchRequest::try_from(&request).map_err(|e| ErrorData::invalid_params(e, None))?;
+        let libraries = match request
+            .prefecture
+            .as_deref()
+            .filter(|p| !p.trim().is_empty())
+        {
+            Some(prefecture) => Some(libraries_in_prefecture(self, prefecture).await?),
+            None => None,
+        };
+        let lib_ids: Option<Vec<String>> = libraries
+            .as_ref()
+            .map(|libraries| libraries.iter().map(|entry| entry.lib_id.clone()).collect());
+        let k = match &libraries {
+            Some(libraries) => search_in_libraries(self, search, libraries, max_records).await?,
+            None => self.search_all(search, max_records).await?,
+        };
+        let i = UnresolvedResponse::new(
+            k,
+            date::today(),
+            request.order,
+            request.limit as usize,
+            lib_ids.as_deref(),
+        );
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "検索結果をページングしながら取得し、CSV・JSONL・TSV(Excel向け)に変換した内容を返す。表計算ソフトでの集計・報告書作成に使用する。"
+    )]
+    pub async fn export(
+        &self,
+        request: Parameters<ExportRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let ExportRequest {
+            search,
+            format,
+            max_records,
+        } = request.0;
+        let max_records = check_max_records(max_records, EXPORT_MAX_RECORDS)?;
+        let i = export(self, search, max_records, format, None)
+            .await
+            .map_err(into_error_data)?;
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "検索結果のレコードが参考資料として挙げている文献を集め、ISBNまたは書誌的事項で重複を除いてRIS・BibTeX・CSL-JSONで出力する。Zoteroなどの文献管理ソフトへの取り込みに使用する。"
+    )]
+    pub async fn bibliography(
+        &self,
+        request: Parameters<BibliographyRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let BibliographyRequest {
+            search,
+            format,
+            max_records,
+        } = request.0;
+        let max_records = check_max_records(max_records, BIBLIOGRAPHY_MAX_RECORDS)?;
+        let i = export_bibliography(self, search, max_records, format)
+            .await
+            .map_err(into_error_data)?;
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "ローカルミラー(syncサブコマンドで作成したデータベース)のレファレンス事例・調べ方マニュアル・特別コレクションを全文検索し、関連度(BM25)の高い順に返す。質問・回答・調べ方・内容・キーワードを対象とする。"
+    )]
+    pub async fn local_search(
+        &self,
+        request: Parameters<LocalSearchRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let i = self
+            .with_mirror_db(move |mirror| mirror.search(&request.0))
+            .await?;
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+
+    #[tool(
+        description = "CRDのレコードに図書館員が付与したキーワードから、語の一部を含むキーワードと、同じレコードに付与されることの多いキーワードを返す。searchの検索語を選ぶ際に使用し、queryのkeyword = の検索式で検索する。語彙は取得したレコードとローカルミラーのキーワードから作る。"
+    )]
+    pub async fn suggest_keywords(
+        &self,
+        request: Parameters<SuggestKeywordsRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let i = self
+            .suggest_keywords_for(&request.0.query, request.0.limit as usize)
+            .await?;
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
+}
+
+#[prompt_router]
+impl CrdService {
+    /// 調べたい事柄の語から、CRDのキーワードの候補と検索式を示す。
+    #[prompt(name = "suggest_keywords")]
+    pub async fn suggest_keywords_prompt(
+        &self,
+        request: Parameters<SuggestKeywordsPrompt>,
+    ) -> Result<Vec<PromptMessage>, ErrorData> {
+        let query = request.0.query;
+        let response = self.suggest_keywords_for(&query, 10).await?;
+        let mut text = format!("CRDで「{query}」について調べます。");
+        if response.suggestions.is_empty() {
+            text.push_str("一致するキーワードが見つからなかったため、searchツールでanywhereを対象に検索してください。");
+        } else {
+            text.push_str("次のキーワードの検索式を手がかりに、searchツールで検索してください。\n");
+            for suggestion in &response.suggestions {
+                text.push_str(&format!(
+                    "\n- {}（{}件）: {}",
+                    suggestion.keyword, suggestion.count, suggestion.query
+                ));
+                if !suggestion.related.is_empty() {
+                    let related: Vec<&str> = suggestion
+                        .related
+                        .iter()
+                        .map(|r| r.keyword.as_str())
+                        .collect();
+                    text.push_str(&format!(" 関連: {}", related.join("、")));
+                }
+            }
+        }
+        Ok(vec![PromptMessage::new_text(PromptMessageRole::User, text)])
+    }
+
+    /// 調べたい事柄と検索対象・クエリー対象項目・分類・提供館から、searchツールの検索条件を組み立てる。
+    #[prompt(name = "search_cases")]
+    pub async fn search_cases_prompt(
+        &self,
+        request: Parameters<SearchCasesPrompt>,
+    ) -> Result<Vec<PromptMessage>, ErrorData> {
+        let SearchCasesPrompt {
+            topic,
+            ty,
+            index,
+            ndc,
+            lib_id,
+            lib_group,
+            prefecture,
+        } = request.0;
+        let invalid = |e: String| ErrorData::invalid_params(e, None);
+        let ty: ReqType = ty
+            .as_deref()
+            .unwrap_or("reference")
+            .parse()
+            .map_err(invalid)?;
+        let lib_group: Option<LibGroup> = lib_group
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map_err(invalid)?;
+        let indexes = completion::cql_indexes(ty);
+        let index = index.unwrap_or_else(|| "anywhere".to_string());
+        if !indexes.contains(&index.as_str()) {
+            return Err(invalid(format!(
+                "クエリー対象項目 {index} は type = {ty} では使用できません"
+            )));
+        }
+        let mut clauses = vec![format!("{index} all {}", quote(&topic))];
+        if let Some(ndc) = ndc.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+            if !indexes.contains(&"ndc") {
+                return Err(invalid(format!("type = {ty} ではndcを指定できません")));
+            }
+            clauses.push(format!("ndc = {}", quote(ndc)));
+        }
+        let prefecture = prefecture
+            .as_deref()
+            .map(|p| {
+                normalize_prefecture(p)
+                    .ok_or_else(|| invalid(format!("都道府県名が正しくありません: {p}")))
+            })
+            .transpose()?;
+
+        let mut text = format!(
+            "CRDで「{topic}」について調べます。searchツールで次の条件を指定して検索してください。\n\n- type: {ty}\n- query: {}",
+            clauses.join(" and ")
+        );
+        if let Some(lib_id) = lib_id {
+            text.push_str(&format!("\n- lib_id: {lib_id}"));
+        }
+        if let Some(lib_group) = lib_group {
+            text.push_str(&format!(
+                "\n- lib_group: {lib_group}（{}）",
+                lib_group.label()
+            ));
+        }
+        if let Some(prefecture) = prefecture {
+            text.push_str(&format!(
+                "\n\n提供館は{prefecture}の参加館に絞り込んでください。find_librariesツールで{prefecture}の参加館の図書館コードを調べ、lib_idに指定します。"
+            ));
+        }
+        Ok(vec![PromptMessage::new_text(PromptMessageRole::User, text)])
+    }
+}
+
+/// 取得する最大件数が1件以上 `max` 件以下であることを確かめる。
+pub(crate) fn check_max_records(max_records: u32, max: u32) -> Result<usize, ErrorData> {
+    if !(1..=max).contains(&max_records) {
+        return Err(ErrorData::invalid_params(
+            format!("max_recordsは1から{max}の範囲で指定してください"),
+            None,
+        ));
+    }
+    Ok(max_records as usize)
+}
+
+/// CRD APIのエラーはそのまま、それ以外は内部エラーとして返す。
+fn into_error_data(e: anyhow::Error) -> ErrorData {
+    match e.downcast::<ErrorData>() {
+        Ok(e) => e,
+        Err(e) => ErrorData::internal_error(e.to_string(), None),
+    }
+}
+
+#[cfg(feature = "embeddings")]
+#[tool_router(router = embedding_router)]
+impl CrdService {
+    #[tool(
+        description = "ローカルミラーのレファレンス事例から、指定した文章または登録番号(sys_id)の事例に意味的に類似する事例を類似度の高い順に返す。表現の異なる過去の類似事例を探す際に使用する。"
+    )]
+    pub async fn similar_cases(
+        &self,
+        request: Parameters<crate::req::SimilarCasesRequest>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let embedder = self.embedder.clone();
+        let i = self
+            .with_mirror_db(move |mirror| {
+                let mut embedder = embedder.lock().unwrap();
+                mirror.similar_cases(&request.0, &mut embedder)
+            })
+            .await?;
+        Ok(CallToolResult::structured(serde_json::to_value(i).unwrap()))
+    }
 }
 
 #[tool_handler]
+#[prompt_handler]
 impl ServerHandler for CrdService {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
             protocol_version: ProtocolVersion::LATEST,
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            capabilities: ServerCapabilities::builder()
+                .enable_completions()
+                .enable_prompts()
+                .enable_tools()
+                .build(),
             server_info: Implementation::from_build_env(),
             instructions: Some(env!("CARGO_PKG_DESCRIPTION").to_string()),
         }
     }
+
+    /// プロンプトの引数の補完
+    ///
+    /// suggest_keywordsのqueryは語彙のキーワードから、図書館コード・都道府県名・NDC分類コード・図書館グループ・
+    /// 検索対象・クエリー対象項目は引数の名前から補完する。クエリー対象項目は指定済みの `type` で使えるものに限る。
+    /// 入力のたびに呼ばれるためCRDは検索せず、ローカルミラーを読み込めない場合もエラーにせず補完を続ける。
+    async fn complete(
+        &self,
+        request: CompleteRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<CompleteResult, ErrorData> {
+        let argument = |name: &str| {
+            request
+                .context
+                .as_ref()
+                .and_then(|context| context.get_argument(name))
+                .map(String::as_str)
+        };
+        let value = request.argument.value.as_str();
+        let mut values = match (&request.r#ref, request.argument.name.as_str()) {
+            (Reference::Prompt(prompt), "query") if prompt.name == "suggest_keywords" => {
+                if let Err(e) = self.load_mirror_vocabulary().await {
+                    tracing::warn!("failed to load keywords from the mirror: {}", e.message);
+                }
+                let vocabulary = self.vocabulary.0.lock().await;
+                vocabulary
+                    .matches(value, CompletionInfo::MAX_VALUES)
+                    .into_iter()
+                    .map(|(keyword, _)| keyword.to_string())
+                    .collect()
+            }
+            (_, "lib_id") => completion::lib_ids(&library_candidates(self).await, value),
+            (_, "prefecture") => completion::prefectures(value),
+            (_, "ndc") => {
+                let edition = NdcEdition::from_version(argument("edition")).unwrap_or_default();
+                completion::ndc_codes(edition, value)
+            }
+            (_, "lib_group") => completion::lib_groups(value),
+            (_, "type") => completion::req_types(value),
+            (_, "index") => {
+                let ty = argument("type").and_then(|ty| ty.parse().ok());
+                completion::indexes(ty, value)
+            }
+            _ => Vec::new(),
+        };
+        let total = values.len();
+        values.truncate(CompletionInfo::MAX_VALUES);
+        Ok(CompleteResult {
+            completion: CompletionInfo {
+                has_more: Some(total > values.len()),
+                total: Some(total as u32),
+                values,
+            },
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::req::{Condition, ReqType};
+    use crate::req::{Condition, RecentBasis, RecentUpdatesRequest, ReqType};
     #[tokio::test]
     async fn test_crd_search() {
         let service = super::CrdService::new();
@@ -71,6 +953,7 @@ mod tests {
             },
             lib_id: None,
             lib_group: None,
+            lib_type: None,
             results_get_position: None,
             results_num: 100,
         };
@@ -94,6 +977,7 @@ mod tests {
             },
             lib_id: None,
             lib_group: None,
+            lib_type: None,
             results_get_position: None,
             results_num: 100,
         };
@@ -117,6 +1001,7 @@ mod tests {
             },
             lib_id: None,
             lib_group: None,
+            lib_type: None,
             results_get_position: None,
             results_num: 100,
         };
@@ -142,10 +1027,61 @@ mod tests {
             },
             lib_id: None,
             lib_group: None,
+            lib_type: None,
             results_get_position: None,
             results_num: 10,
         };
         let res = service.crd_search(req).await.unwrap();
         assert_eq!(res.hit_num.unwrap(), 0);
     }
+
+    #[tokio::test]
+    async fn test_crd_search_all() {
+        let service = super::CrdService::new();
+        let req = RecentUpdatesRequest {
+            ty: ReqType::Reference,
+            since: "20240401".to_string(),
+            until: Some("20240410".to_string()),
+            basis: RecentBasis::Registered,
+            lib_id: None,
+            lib_group: None,
+            max_records: 150,
+        };
+        let res = service
+            .search_all(req.try_into().unwrap(), 150)
+            .await
+            .unwrap();
+        assert!(res.results.len() <= 150);
+        assert_eq!(res.results.len(), (res.hit_count as usize).min(150),);
+    }
+
+    #[tokio::test]
+    async fn test_search_sorted_cache() {
+        let service = super::CrdService::new();
+        let request: crate::req::SearchRequest = serde_json::from_value(serde_json::json!({
+            "type": "reference",
+            "query": "question any 北海道",
+            "results_num": 1,
+            "sort_by": "created_at",
+            "order": "asc",
+        }))
+        .unwrap();
+        let cursor = super::SearchCursor::from_request(request).unwrap();
+        let set = crate::crd::CrdResultSet::parse(include_str!("fixtures/reference.xml")).unwrap();
+        let response = Result::<super::CrdSearchResponse, super::ErrorData>::from(set).unwrap();
+        service.sort_cache.0.lock().await.push_back((
+            cursor.fingerprint().to_string(),
+            std::sync::Arc::new(response),
+        ));
+        // キャッシュしたレコードから、CRDを検索せずに各ページを返す
+        let first = service.search_page(&cursor).await.unwrap();
+        assert_eq!(first.results.len(), 1);
+        assert!(first.next_cursor.is_some());
+        let next = super::SearchCursor::decode(first.next_cursor.as_deref().unwrap()).unwrap();
+        assert_eq!(next.fingerprint(), cursor.fingerprint());
+        let second = service.search_page(&next).await.unwrap();
+        assert_eq!(second.cursor_position, 1);
+        assert_ne!(first.results[0].sys_id(), second.results[0].sys_id());
+        assert!(!second.has_more);
+    }
 }
@@ -0,0 +1,280 @@
+use crate::crd::{CrdResult, CrdResultSet};
+use crate::library::quote;
+use crate::query::normalize;
+use crate::res::{KeywordSuggestion, RelatedKeyword};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// 語彙に加えるレコード数の上限
+const MAX_RECORDS: usize = 100_000;
+
+/// キーワードの出現回数と、同じレコードに付与されたキーワードの出現回数
+#[derive(Debug)]
+struct KeywordStats {
+    /// 照合用に正規化したキーワード
+    normalized: String,
+    count: usize,
+    related: HashMap<String, usize>,
+}
+
+/// CRDのレコードに付与されたキーワードの語彙
+///
+/// 取得したレコードとローカルミラーのレコードのキーワードを、登録番号ごとに1回だけ数える。
+/// レコード数が上限を超えた場合は、最も長く取得されていないレコードのキーワードを語彙から外す。
+#[derive(Debug)]
+pub struct Vocabulary {
+    /// 語彙に加えたレコードの登録番号と、最後に取得した順番・付与されたキーワード
+    records: HashMap<String, (u64, Vec<String>)>,
+    /// レコードを取得した順番。取得し直したレコードの古い順番は、取り出す際に読み飛ばす。
+    recent: VecDeque<(u64, String)>,
+    sequence: u64,
+    max_records: usize,
+    keywords: HashMap<String, KeywordStats>,
+    /// ローカルミラーのキーワードを読み込んだ場合はtrue
+    pub mirror_loaded: bool,
+}
+
+impl Default for Vocabulary {
+    fn default() -> Self {
+        Vocabulary::with_max_records(MAX_RECORDS)
+    }
+}
+
+impl Vocabulary {
+    /// レコード数の上限を指定して語彙を作る。
+    pub fn with_max_records(max_records: usize) -> Vocabulary {
+        Vocabulary {
+            records: HashMap::new(),
+            recent: VecDeque::new(),
+            sequence: 0,
+            max_records: max_records.max(1),
+            keywords: HashMap::new(),
+            mirror_loaded: false,
+        }
+    }
+
+    /// レコードのキーワードを語彙に加える。既に加えたレコードは数えず、取得した順番のみ更新する。
+    pub fn add(&mut self, sys_id: &str, keywords: &[String]) {
+        if sys_id.is_empty() {
+            return;
+        }
+        self.sequence += 1;
+        self.recent.push_back((self.sequence, sys_id.to_string()));
+        if let Some((sequence, _)) = self.records.get_mut(sys_id) {
+            *sequence = self.sequence;
+            self.compact();
+            return;
+        }
+        let mut unique: Vec<String> = Vec::new();
+        for keyword in keywords.iter().map(|k| k.trim()) {
+            if !keyword.is_empty() && !unique.iter().any(|k| k == keyword) {
+                unique.push(keyword.to_string());
+            }
+        }
+        for keyword in &unique {
+            let stats = self
+                .keywords
+                .entry(keyword.clone())
+                .or_insert_with(|| KeywordStats {
+                    normalized: normalize(keyword),
+                    count: 0,
+                    related: HashMap::new(),
+                });
+            stats.count += 1;
+            for other in unique.iter().filter(|other| *other != keyword) {
+                *stats.related.entry(other.clone()).or_default() += 1;
+            }
+        }
+        self.records
+            .insert(sys_id.to_string(), (self.sequence, unique));
+        while self.records.len() > self.max_records {
+            self.evict();
+        }
+        self.compact();
+    }
+
+    /// 最も長く取得されていないレコードのキーワードを語彙から外す。
+    fn evict(&mut self) {
+        while let Some((sequence, sys_id)) = self.recent.pop_front() {
+            if self
+                .records
+                .get(&sys_id)
+                .is_some_and(|(s, _)| *s == sequence)
+            {
+                let (_, keywords) = self.records.remove(&sys_id).unwrap_or_default();
+                self.remove_keywords(&keywords);
+                return;
+            }
+        }
+    }
+
+    fn remove_keywords(&mut self, keywords: &[String]) {
+        for keyword in keywords {
+            let Some(stats) = self.keywords.get_mut(keyword) else {
+                continue;
+            };
+            stats.count -= 1;
+            if stats.count == 0 {
+                self.keywords.remove(keyword);
+                continue;
+            }
+            for other in keywords.iter().filter(|other| *other != keyword) {
+                if let Some(count) = stats.related.get_mut(other) {
+                    *count -= 1;
+                    if *count == 0 {
+                        stats.related.remove(other);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 取得し直したレコードの古い順番が溜まった場合に取り除く。
+    fn compact(&mut self) {
+        if self.recent.len() > self.records.len() * 2 {
+            let records = &self.records;
+            self.recent.retain(|(sequence, sys_id)| {
+                records.get(sys_id).is_some_and(|(s, _)| s == sequence)
+            });
+        }
+    }
+
+    /// 検索結果のレコードのキーワードを語彙に加える。
+    pub fn add_result_set(&mut self, set: &CrdResultSet) {
+        for result in set.result.iter().flatten() {
+            let (system, keywords) = match result {
+                CrdResult::Reference(r) => (&r.system, &r.keywords),
+                CrdResult::Manual(r) => (&r.system, &r.keywords),
+                CrdResult::Collection(r) => (&r.system, &r.keywords),
+                CrdResult::Profile(_) => continue,
+            };
+            if let Some(keywords) = keywords {
+                self.add(&system.sys_id, keywords);
+            }
+        }
+    }
+
+    /// 語彙に加えたレコード数
+    pub fn record_count(&self) -> usize {
+        self.records.len()
+    }
+
+    /// 語彙のキーワードの数
+    pub fn keyword_count(&self) -> usize {
+        self.keywords.len()
+    }
+
+    /// 部分的な語を含むキーワードを、前方一致するもの・付与されたレコードの多いものの順に返す。
+    ///
+    /// 全角・半角、旧字体・新字体、大文字・小文字の違いは無視する。
+    pub fn matches(&self, term: &str, limit: usize) -> Vec<(&str, usize)> {
+        let term = normalize(term.trim());
+        let mut matches: Vec<(bool, &str, usize)> = self
+            .keywords
+            .iter()
+            .filter(|(_, stats)| stats.normalized.contains(&term))
+            .map(|(keyword, stats)| {
+                (
+                    stats.normalized.starts_with(&term),
+                    keyword.as_str(),
+                    stats.count,
+                )
+            })
+            .collect();
+        matches.sort_by(|a, b| b.0.cmp(&a.0).then(b.2.cmp(&a.2)).then(a.1.cmp(b.1)));
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, keyword, count)| (keyword, count))
+            .collect()
+    }
+
+    /// 部分的な語を含むキーワードと、それぞれと同じレコードに付与されることの多いキーワードを返す。
+    pub fn suggest(
+        &self,
+        term: &str,
+        limit: usize,
+        related_limit: usize,
+    ) -> Vec<KeywordSuggestion> {
+        self.matches(term, limit)
+            .into_iter()
+            .map(|(keyword, count)| {
+                let mut related: Vec<(&String, &usize)> =
+                    self.keywords[keyword].related.iter().collect();
+                related.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+                KeywordSuggestion {
+                    keyword: keyword.to_string(),
+                    count,
+                    related: related
+                        .into_iter()
+                        .take(related_limit)
+                        .map(|(keyword, count)| RelatedKeyword {
+                            keyword: keyword.clone(),
+                            count: *count,
+                        })
+                        .collect(),
+                    query: format!("keyword = {}", quote(keyword)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// サービス全体で共有するキーワードの語彙
+#[derive(Debug, Clone, Default)]
+pub struct VocabularyCache(pub Arc<Mutex<Vocabulary>>);
+
+#[cfg(test)]
+mod tests {
+    use super::Vocabulary;
+
+    fn keywords(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_suggest() {
+        let mut vocabulary = Vocabulary::default();
+        vocabulary.add("1", &keywords(&["郷土資料", "北海道", "地域資料"]));
+        vocabulary.add("2", &keywords(&["郷土資料", "北海道"]));
+        vocabulary.add("3", &keywords(&["郷土史", "札幌市"]));
+        vocabulary.add("4", &keywords(&["地域の郷土資料 "]));
+        // 同じレコードは数えない
+        vocabulary.add("1", &keywords(&["郷土資料", "北海道", "地域資料"]));
+        assert_eq!(vocabulary.record_count(), 4);
+        assert_eq!(vocabulary.keyword_count(), 6);
+
+        assert_eq!(
+            vocabulary.matches("郷土", 10),
+            [("郷土資料", 2), ("郷土史", 1), ("地域の郷土資料", 1)]
+        );
+        assert_eq!(vocabulary.matches("鄕土", 1), [("郷土資料", 2)]);
+
+        let suggestions = vocabulary.suggest("郷土資", 1, 5);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].keyword, "郷土資料");
+        assert_eq!(suggestions[0].related[0].keyword, "北海道");
+        assert_eq!(suggestions[0].related[0].count, 2);
+        assert_eq!(suggestions[0].related[1].keyword, "地域資料");
+        assert_eq!(suggestions[0].query, "keyword = \"郷土資料\"");
+        assert!(vocabulary.suggest("存在しない", 10, 5).is_empty());
+    }
+
+    #[test]
+    fn test_max_records() {
+        let mut vocabulary = Vocabulary::with_max_records(2);
+        vocabulary.add("1", &keywords(&["郷土資料", "北海道"]));
+        vocabulary.add("2", &keywords(&["郷土資料", "札幌市"]));
+        // 取得し直したレコードは外さない
+        vocabulary.add("1", &keywords(&["郷土資料", "北海道"]));
+        vocabulary.add("3", &keywords(&["地域資料"]));
+        assert_eq!(vocabulary.record_count(), 2);
+        assert_eq!(vocabulary.keyword_count(), 3);
+        assert_eq!(vocabulary.matches("郷土", 10), [("郷土資料", 1)]);
+        assert!(vocabulary.matches("札幌", 10).is_empty());
+        let suggestions = vocabulary.suggest("郷土資料", 1, 5);
+        assert_eq!(suggestions[0].related.len(), 1);
+        assert_eq!(suggestions[0].related[0].keyword, "北海道");
+    }
+}